@@ -18,12 +18,9 @@
 
 @KEYBOARD@
 
-  # Define a user account. Don't forget to set a password with ‘passwd’.
-  users.users."@USERNAME@" = {
-    isNormalUser = true;
-    description = "@FULLNAME@";
-    extraGroups = [ "wheel" "networkmanager" "dialout" ];
-  };
+@USERS@
+@ROOTPASSWORD@
+@OPENSSH@
   @AUTOLOGIN@
 
   # Allow unfree packages
@@ -31,6 +28,8 @@
 
   @PACKAGES@
 
+@SUBSTITUTERS@
+
   # This value determines the NixOS release from which the default
   # settings for stateful data, like file locations and database versions
   # on your system were taken. It‘s perfectly fine and recommended to leave
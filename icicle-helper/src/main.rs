@@ -6,8 +6,10 @@ use distinst_disks::{DiskExt, PartitionBuilder, PartitionFlag};
 use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
-    fs::{self, File},
+    fs::{self, File, OpenOptions},
     io::{self, Read, Write},
+    os::unix::fs::{OpenOptionsExt, PermissionsExt},
+    path::{Component, Path},
     process::Command,
 };
 
@@ -48,9 +50,30 @@ enum SubCommands {
         #[clap(short, long)]
         contents: String,
     },
+    /// Writes stdin to a path inside /tmp/icicle
+    InstallFile {
+        #[clap(short, long)]
+        path: String,
+        #[clap(short, long, default_value = "0644")]
+        mode: String,
+        #[clap(short, long)]
+        owner: Option<String>,
+    },
     Unmount {},
+    /// Releases and removes /tmp/icicle before partitioning
+    Clear {},
+    /// Stops a running install step and releases the target system
+    Cancel {
+        /// Process started by the install step, stopped with all its children
+        #[clap(long)]
+        pid: Option<i32>,
+    },
 }
 
+/// Swap partitions enabled while partitioning, one per line. Kept in /run,
+/// where only root can create files.
+const SWAPS_PATH: &str = "/run/icicle-swaps";
+
 fn main() {
     let cli =
         SubCommands::augment_subcommands(clap::Command::new("Helper binary for Icicle installer"));
@@ -110,6 +133,12 @@ fn main() {
             let mut file = File::create(path).unwrap();
             file.write_all(contents.as_bytes()).unwrap();
         }
+        SubCommands::InstallFile { path, mode, owner } => {
+            if let Err(e) = install_file(&path, &mode, owner.as_deref()) {
+                eprintln!("{:?}", e);
+                std::process::exit(1);
+            }
+        }
         SubCommands::Unmount {} => {
             if let Err(e) = Command::new("umount")
                 .arg("-R")
@@ -121,7 +150,223 @@ fn main() {
                 std::process::exit(1);
             }
         }
+        SubCommands::Clear {} => {
+            if let Err(e) = clear() {
+                eprintln!("{:?}", e);
+                std::process::exit(1);
+            }
+        }
+        SubCommands::Cancel { pid } => {
+            if let Err(e) = cancel(pid) {
+                eprintln!("{:?}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+fn cancel(pid: Option<i32>) -> Result<()> {
+    if let Some(pid) = pid {
+        let mut pids = descendants(pid);
+        pids.push(pid);
+        println!("Cancel: Stopping processes {:?}", pids);
+        signal("TERM", &pids)?;
+        // Give nix a moment to clean up before killing whatever is left
+        for _ in 0..50 {
+            if !pids.iter().any(|pid| running(*pid)) {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+        let remaining = pids
+            .into_iter()
+            .filter(|pid| running(*pid))
+            .collect::<Vec<_>>();
+        if !remaining.is_empty() {
+            signal("KILL", &remaining)?;
+        }
+    }
+    release()
+}
+
+/// Disables the swap enabled while partitioning and unmounts /tmp/icicle
+fn release() -> Result<()> {
+    if let Ok(swaps) = fs::read_to_string(SWAPS_PATH) {
+        for swap in swaps.lines().filter(|line| !line.is_empty()) {
+            println!("Release: Disabling swap on {}", swap);
+            let output = Command::new("swapoff")
+                .arg(swap)
+                .output()
+                .context("Failed to disable swap")?;
+            if !output.status.success() {
+                eprintln!("{}", String::from_utf8_lossy(&output.stderr).trim());
+            }
+        }
+        fs::remove_file(SWAPS_PATH)?;
     }
+
+    println!("Release: Unmounting /tmp/icicle");
+    Command::new("umount")
+        .arg("-R")
+        .arg("-f")
+        .arg("/tmp/icicle")
+        .output()
+        .context("Failed to unmount /tmp/icicle")?;
+    Ok(())
+}
+
+/// Releases /tmp/icicle and removes it, unless something is still mounted
+/// there that would be removed with it
+fn clear() -> Result<()> {
+    release()?;
+    let mounts = fs::read_to_string("/proc/mounts")?;
+    if mounts.lines().any(|line| {
+        line.split_whitespace()
+            .nth(1)
+            .is_some_and(|target| target == "/tmp/icicle" || target.starts_with("/tmp/icicle/"))
+    }) {
+        return Err(anyhow!("/tmp/icicle is still mounted"));
+    }
+    println!("Clear: Removing /tmp/icicle");
+    match fs::remove_dir_all("/tmp/icicle") {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result.context("Failed to remove /tmp/icicle"),
+    }
+}
+
+/// Every process below `pid`, read from /proc
+fn descendants(pid: i32) -> Vec<i32> {
+    let mut parents = HashMap::new();
+    if let Ok(entries) = fs::read_dir("/proc") {
+        for entry in entries.flatten() {
+            let Some(child) = entry
+                .file_name()
+                .to_str()
+                .and_then(|x| x.parse::<i32>().ok())
+            else {
+                continue;
+            };
+            // The parent is the second field after the command name in parentheses
+            let ppid = fs::read_to_string(entry.path().join("stat"))
+                .ok()
+                .and_then(|stat| {
+                    stat.rsplit_once(')')
+                        .and_then(|(_, rest)| rest.split_whitespace().nth(1)?.parse::<i32>().ok())
+                });
+            if let Some(ppid) = ppid {
+                parents.entry(ppid).or_insert_with(Vec::new).push(child);
+            }
+        }
+    }
+    let mut pids = vec![];
+    let mut queue = vec![pid];
+    while let Some(parent) = queue.pop() {
+        if let Some(children) = parents.get(&parent) {
+            pids.extend(children);
+            queue.extend(children);
+        }
+    }
+    pids
+}
+
+/// Whether a process exists and is not a zombie
+fn running(pid: i32) -> bool {
+    fs::read_to_string(format!("/proc/{}/stat", pid))
+        .ok()
+        .and_then(|stat| {
+            stat.rsplit_once(')')
+                .and_then(|(_, rest)| rest.split_whitespace().next().map(|state| state != "Z"))
+        })
+        .unwrap_or(false)
+}
+
+fn signal(signal: &str, pids: &[i32]) -> Result<()> {
+    Command::new("kill")
+        .arg(format!("-{}", signal))
+        .args(pids.iter().map(|pid| pid.to_string()))
+        .output()
+        .context("Failed to stop processes")?;
+    Ok(())
+}
+
+fn install_file(path: &str, mode: &str, owner: Option<&str>) -> Result<()> {
+    let relpath = Path::new(path.trim_start_matches('/'));
+    if relpath
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_)))
+    {
+        return Err(anyhow!("Invalid path {}", path));
+    }
+    let filename = relpath
+        .file_name()
+        .with_context(|| format!("Invalid path {}", path))?;
+    let mode = u32::from_str_radix(mode, 8).with_context(|| format!("Invalid mode {}", mode))?;
+
+    let mut contents = vec![];
+    io::stdin().lock().read_to_end(&mut contents)?;
+
+    // Symlinks in the installed system point into it, not into the live
+    // system, so every directory is resolved and checked before it is used
+    let root = fs::canonicalize("/tmp/icicle")?;
+    let mut parent = root.clone();
+    for component in relpath.parent().into_iter().flat_map(Path::components) {
+        let dir = parent.join(component);
+        if fs::symlink_metadata(&dir).is_err() {
+            fs::create_dir(&dir)
+                .with_context(|| format!("Failed to create {}", dir.to_string_lossy()))?;
+        }
+        parent = fs::canonicalize(&dir)?;
+        if !parent.starts_with(&root) {
+            return Err(anyhow!("{} is outside of /tmp/icicle", path));
+        }
+    }
+    let target = parent.join(filename);
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(mode)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(&target)
+        .with_context(|| format!("Failed to write {}", target.to_string_lossy()))?;
+    file.write_all(&contents)
+        .with_context(|| format!("Failed to write {}", target.to_string_lossy()))?;
+    // The mode given to open is only used for new files and masked by the umask
+    file.set_permissions(fs::Permissions::from_mode(mode))?;
+
+    if let Some(owner) = owner {
+        let (user, group) = match owner.split_once(':') {
+            Some((user, group)) => (user, Some(group)),
+            None => (owner, None),
+        };
+        let uid = lookup_id("/tmp/icicle/etc/passwd", user)?;
+        let gid = match group {
+            Some(group) => Some(lookup_id("/tmp/icicle/etc/group", group)?),
+            None => None,
+        };
+        std::os::unix::fs::fchown(&file, Some(uid), gid)
+            .with_context(|| format!("Failed to change owner of {}", path))?;
+    }
+    Ok(())
+}
+
+/// Id of a user or group of the installed system, looked up by name in its
+/// passwd or group file
+fn lookup_id(file: &str, name: &str) -> Result<u32> {
+    if let Ok(id) = name.parse() {
+        return Ok(id);
+    }
+    fs::read_to_string(file)?
+        .lines()
+        .find_map(|line| {
+            let mut fields = line.split(':');
+            if fields.next()? == name {
+                fields.nth(1)?.parse().ok()
+            } else {
+                None
+            }
+        })
+        .with_context(|| format!("No {} in {}", name, file))
 }
 
 fn partition() -> Result<()> {
@@ -332,10 +577,21 @@ fn partition() -> Result<()> {
             });
             for (part, custom) in mountvec {
                 if custom.format == Some("swap".to_string()) {
-                    let _output = Command::new("swapon")
+                    let output = Command::new("swapon")
                         .arg(&part)
                         .output()
                         .context("Failed to enable swap")?;
+                    if output.status.success() {
+                        // Remembered so that a cancelled installation can disable it again
+                        let mut swaps = OpenOptions::new()
+                            .create(true)
+                            .append(true)
+                            .mode(0o600)
+                            .custom_flags(libc::O_NOFOLLOW)
+                            .open(SWAPS_PATH)
+                            .with_context(|| format!("Failed to open {}", SWAPS_PATH))?;
+                        writeln!(swaps, "{}", part)?;
+                    }
                     continue;
                 }
                 if let Some(target) = custom.mountpoint {
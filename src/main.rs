@@ -1,19 +1,40 @@
 use adw::gio;
+use clap::Parser;
 use gettextrs::{gettext, LocaleCategory};
 use gtk::{glib, prelude::ApplicationExt};
 use icicle::{
     config::{GETTEXT_PACKAGE, LOCALEDIR, RESOURCES_FILE},
     ui::window::AppModel,
+    utils::unattended,
 };
 use log::{error, info};
 use relm4::*;
 use simplelog::*;
 use std::fs::File;
 
+#[derive(Parser, Debug)]
+#[clap(version, about = "A graphical installer for NixOS based distributions")]
+struct Args {
+    /// Install without a window using a YAML or JSON answer file
+    #[clap(long, value_name = "FILE")]
+    answers: Option<String>,
+    /// Only render the configuration produced by the answer file into DIR
+    #[clap(long, value_name = "DIR", requires = "answers")]
+    dry_run: Option<String>,
+    /// Continue a failed unattended installation from the step that failed
+    #[clap(long, requires = "answers", conflicts_with = "dry_run")]
+    resume: bool,
+}
+
 fn main() {
+    let args = Args::parse();
     CombinedLogger::init(vec![
         TermLogger::new(
-            LevelFilter::Warn,
+            if args.answers.is_some() {
+                LevelFilter::Info
+            } else {
+                LevelFilter::Warn
+            },
             Config::default(),
             TerminalMode::Mixed,
             ColorChoice::Auto,
@@ -25,6 +46,20 @@ fn main() {
         ),
     ])
     .unwrap();
+
+    if let Some(answers) = args.answers {
+        let result = if let Some(output) = args.dry_run {
+            unattended::dry_run(&answers, &output)
+        } else {
+            unattended::install(&answers, args.resume)
+        };
+        if let Err(e) = result {
+            error!("{:?}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     gtk::init().unwrap();
     setup_gettext();
     glib::set_application_name(&gettext("Icicle Installer"));
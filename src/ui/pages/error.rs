@@ -1,14 +1,21 @@
-use crate::{config::LIBEXECDIR, ui::window::AppMsg};
+use crate::{
+    ui::window::AppMsg,
+    utils::{
+        eventlog::{last_failure, Event, EVENT_LOG_PATH},
+        i18n::i18n_f,
+    },
+};
 use adw::prelude::*;
 use anyhow::{Context, Result};
 use gettextrs::gettext;
 use log::error;
 use relm4::*;
-use std::process::Command;
 use tokio::io::AsyncWriteExt;
 
 pub struct ErrorModel {
     messegebuffer: gtk::TextBuffer,
+    /// Description of the step that failed, if known
+    failedstep: Option<String>,
     uploadbutton: UploadButton,
     url: String,
     spinner: gtk::Spinner,
@@ -55,6 +62,14 @@ impl SimpleComponent for ErrorModel {
                         set_icon_name: Some("process-stop-symbolic"),
                         set_pixel_size: 128,
                     },
+                    gtk::Label {
+                        #[watch]
+                        set_visible: model.failedstep.is_some(),
+                        #[watch]
+                        // Translators: Do NOT translate the '{}'
+                        set_label: &i18n_f("Failed step: {}", &[model.failedstep.as_deref().unwrap_or_default()]),
+                        set_wrap: true,
+                    },
                     gtk::Frame {
                         gtk::ScrolledWindow {
                             set_height_request: 300,
@@ -71,34 +86,47 @@ impl SimpleComponent for ErrorModel {
                             }
                         }
                     },
-                    match &model.uploadbutton {
-                        UploadButton::Button => {
-                            gtk::Button {
-                                add_css_class: "pill",
-                                set_halign: gtk::Align::Center,
-                                #[watch]
-                                set_label: &gettext("Upload Report"),
-                                connect_clicked[sender] => move |_| {
-                                    sender.input(ErrorMsg::UploadReport);
-                                }
-                            }
-                        },
-                        UploadButton::Loading => {
-                            #[local]
-                            spinner -> gtk::Spinner {
-                                set_spinning: true,
-                                set_halign: gtk::Align::Center,
-                                set_size_request: (48, 48),
+                    gtk::Box {
+                        set_orientation: gtk::Orientation::Horizontal,
+                        set_halign: gtk::Align::Center,
+                        set_spacing: 10,
+                        gtk::Button {
+                            set_css_classes: &["pill", "suggested-action"],
+                            #[watch]
+                            set_label: &gettext("Retry"),
+                            connect_clicked[sender] => move |_| {
+                                let _ = sender.output(AppMsg::Retry);
                             }
                         },
-                        UploadButton::Url => {
-                            gtk::LinkButton {
-                                set_css_classes: &["pill", "suggested-action"],
-                                #[watch]
-                                set_label: &gettext("Open Report"),
-                                #[watch]
-                                set_uri: &model.url,
-                                set_halign: gtk::Align::Center,
+                        match &model.uploadbutton {
+                            UploadButton::Button => {
+                                gtk::Button {
+                                    add_css_class: "pill",
+                                    set_halign: gtk::Align::Center,
+                                    #[watch]
+                                    set_label: &gettext("Upload Report"),
+                                    connect_clicked[sender] => move |_| {
+                                        sender.input(ErrorMsg::UploadReport);
+                                    }
+                                }
+                            },
+                            UploadButton::Loading => {
+                                #[local]
+                                spinner -> gtk::Spinner {
+                                    set_spinning: true,
+                                    set_halign: gtk::Align::Center,
+                                    set_size_request: (48, 48),
+                                }
+                            },
+                            UploadButton::Url => {
+                                gtk::LinkButton {
+                                    set_css_classes: &["pill", "suggested-action"],
+                                    #[watch]
+                                    set_label: &gettext("Open Report"),
+                                    #[watch]
+                                    set_uri: &model.url,
+                                    set_halign: gtk::Align::Center,
+                                }
                             }
                         }
                     }
@@ -116,6 +144,7 @@ impl SimpleComponent for ErrorModel {
             uploadbutton: UploadButton::Button,
             url: String::new(),
             messegebuffer: gtk::TextBuffer::new(None),
+            failedstep: None,
             spinner: gtk::Spinner::new(),
         };
         let spinner = model.spinner.clone();
@@ -127,15 +156,23 @@ impl SimpleComponent for ErrorModel {
     fn update(&mut self, msg: Self::Input, sender: ComponentSender<Self>) {
         match msg {
             ErrorMsg::Show => {
-                if let Err(e) = Command::new("pkexec")
-                    .arg(&format!("{}/icicle-helper", LIBEXECDIR))
-                    .arg("unmount")
-                    .output()
+                // Partitions stay mounted so that the failed step can be retried
+                let mut outlog = String::new();
+                self.failedstep = None;
+                if let Some((
+                    timestamp,
+                    Event::StepFailed {
+                        description, error, ..
+                    },
+                )) = last_failure()
                 {
-                    error!("Failed to unmount partitions: {}", e);
+                    outlog.push_str(&format!(
+                        "=== Failed Step ===\n{} at {}: {}\n\n",
+                        description, timestamp, error
+                    ));
+                    self.failedstep = Some(description);
                 }
-
-                let mut outlog = "=== Icicle Log ===\n".to_string();
+                outlog.push_str("=== Icicle Log ===\n");
                 if let Ok(iciclelog) = std::fs::read_to_string("/tmp/icicle.log") {
                     outlog.push_str(iciclelog.trim());
                 } else {
@@ -149,6 +186,13 @@ impl SimpleComponent for ErrorModel {
                     outlog.push_str("No log found!");
                 }
                 outlog.push_str("\n=== End of nixos-install Log ===\n\n");
+                outlog.push_str("=== Install Events ===\n");
+                if let Ok(events) = std::fs::read_to_string(EVENT_LOG_PATH) {
+                    outlog.push_str(events.trim());
+                } else {
+                    outlog.push_str("No log found!");
+                }
+                outlog.push_str("\n=== End of Install Events ===\n\n");
                 self.messegebuffer.set_text(&outlog);
             }
             ErrorMsg::UploadReport => {
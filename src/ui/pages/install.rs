@@ -1,24 +1,43 @@
-use crate::{config::SYSCONFDIR, ui::window::AppMsg, utils::parse::parse_branding};
+use crate::{
+    config::SYSCONFDIR,
+    ui::window::AppMsg,
+    utils::{
+        i18n::{i18n_f, ni18n_f},
+        parse::parse_branding,
+        progress::{InstallProgress, Phase, INSTALL_LOG_PATH},
+    },
+};
 use adw::prelude::*;
 use gettextrs::gettext;
 use gtk::gio;
 use log::{debug, error};
 use relm4::{factory::*, *};
-use std::fs::File;
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+};
 use vte::{self, TerminalExt, TerminalExtManual};
 
 pub struct InstallModel {
     terminal: vte::Terminal,
     progressbar: gtk::ProgressBar,
     showterminal: bool,
-    installing: bool,
     slides: FactoryVecDeque<InstallSlide>,
     locale: Option<String>,
+    progress: InstallProgress,
+    /// Whether the output of nixos-install is being followed
+    following: bool,
+    /// How much of the nixos-install log was read, and the last incomplete line
+    logoffset: u64,
+    logline: String,
+    /// Whether the installation is being cancelled
+    cancelling: bool,
 }
 
 #[derive(Debug)]
 pub enum InstallMsg {
-    Pulse,
+    ReadProgress,
+    SetPhase(Phase),
     NextSlide,
     ToggleTerminal,
     Echo(String),
@@ -26,6 +45,7 @@ pub enum InstallMsg {
     VTEOutput(i32),
     SetLocale(Option<String>),
     PostInstall(Vec<String>),
+    Cancel,
 }
 
 pub static INSTALL_BROKER: MessageBroker<InstallMsg> = MessageBroker::new();
@@ -78,12 +98,40 @@ impl SimpleComponent for InstallModel {
                 gtk::Box {
                     set_orientation: gtk::Orientation::Horizontal,
                     set_spacing: 20,
-                    #[local_ref]
-                    progressbar -> gtk::ProgressBar {
+                    gtk::Box {
+                        set_orientation: gtk::Orientation::Vertical,
+                        set_spacing: 5,
                         set_hexpand: true,
                         set_valign: gtk::Align::Center,
-                        set_halign: gtk::Align::Fill,
-                        set_pulse_step: 0.02,
+                        #[local_ref]
+                        progressbar -> gtk::ProgressBar {
+                            set_hexpand: true,
+                            set_halign: gtk::Align::Fill,
+                            #[watch]
+                            set_fraction: model.progress.fraction(),
+                        },
+                        gtk::Label {
+                            set_halign: gtk::Align::Start,
+                            add_css_class: "dim-label",
+                            #[watch]
+                            set_label: &if model.cancelling {
+                                gettext("Cancelling installation…")
+                            } else {
+                                progress_label(&model.progress)
+                            },
+                        },
+                    },
+                    gtk::Button {
+                        set_valign: gtk::Align::Center,
+                        add_css_class: "circular",
+                        set_icon_name: "process-stop-symbolic",
+                        #[watch]
+                        set_tooltip_text: Some(&gettext("Cancel Installation")),
+                        #[watch]
+                        set_sensitive: !model.cancelling,
+                        connect_clicked[sender] => move |_| {
+                            let _ = sender.output(AppMsg::ConfirmCancel);
+                        }
                     },
                     gtk::Button {
                         set_valign: gtk::Align::Center,
@@ -107,11 +155,15 @@ impl SimpleComponent for InstallModel {
             terminal: vte::Terminal::new(),
             showterminal: false,
             progressbar: gtk::ProgressBar::new(),
-            installing: false,
             slides: FactoryVecDeque::builder()
                 .launch_default()
                 .detach(),
             locale: None,
+            progress: InstallProgress::new(),
+            following: false,
+            logoffset: 0,
+            logline: String::new(),
+            cancelling: false,
         };
 
         if let Ok(brandingconfig) = parse_branding(&branding) {
@@ -135,11 +187,11 @@ impl SimpleComponent for InstallModel {
         let progressbar = &model.progressbar;
         let carousel = model.slides.widget();
         let widgets = view_output!();
-        let pulsesender = sender.clone();
+        let progresssender = sender.clone();
         relm4::spawn(async move {
             loop {
-                pulsesender.input(InstallMsg::Pulse);
-                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+                progresssender.input(InstallMsg::ReadProgress);
+                tokio::time::sleep(std::time::Duration::from_millis(500)).await;
             }
         });
         relm4::spawn(async move {
@@ -153,8 +205,14 @@ impl SimpleComponent for InstallModel {
 
     fn update(&mut self, msg: Self::Input, sender: ComponentSender<Self>) {
         match msg {
-            InstallMsg::Pulse => {
-                self.progressbar.pulse();
+            InstallMsg::ReadProgress => {
+                if self.following {
+                    self.read_log();
+                }
+            }
+            InstallMsg::SetPhase(phase) => {
+                self.cancelling = false;
+                self.progress.set_phase(phase);
             }
             InstallMsg::NextSlide => {
                 let npages = self.slides.widget().n_pages();
@@ -183,7 +241,11 @@ impl SimpleComponent for InstallModel {
             }
             InstallMsg::Install(cmds) => {
                 debug!("Installing: {:?}", cmds);
-                self.installing = true;
+                self.progress = InstallProgress::new();
+                self.progress.set_phase(Phase::Evaluating);
+                self.following = true;
+                self.logoffset = 0;
+                self.logline.clear();
                 let cmds: Vec<&str> = cmds.iter().map(|x| &**x).collect();
                 self.terminal.spawn_async(
                     vte::PtyFlags::DEFAULT,
@@ -194,12 +256,16 @@ impl SimpleComponent for InstallModel {
                     || (),
                     -1,
                     gio::Cancellable::NONE,
-                    |err| (debug!("VTE Install: {:?}", err)),
+                    move |res| {
+                        debug!("VTE Install: {:?}", res);
+                        if let Ok(pid) = res {
+                            let _ = sender.output(AppMsg::CommandSpawned(pid.0));
+                        }
+                    },
                 );
             }
             InstallMsg::PostInstall(cmds) => {
                 debug!("PostInstall command: {:?}", cmds);
-                self.installing = false;
                 let cmds: Vec<&str> = cmds.iter().map(|x| &**x).collect();
                 self.terminal.spawn_async(
                     vte::PtyFlags::DEFAULT,
@@ -210,11 +276,23 @@ impl SimpleComponent for InstallModel {
                     || (),
                     -1,
                     gio::Cancellable::NONE,
-                    |err| (debug!("VTE postinstall: {:?}", err)),
+                    move |res| {
+                        debug!("VTE postinstall: {:?}", res);
+                        if let Ok(pid) = res {
+                            let _ = sender.output(AppMsg::CommandSpawned(pid.0));
+                        }
+                    },
                 );
             }
+            InstallMsg::Cancel => {
+                self.cancelling = true;
+            }
             InstallMsg::VTEOutput(status) => {
                 debug!("VTE command exited with status: {}", status);
+                if self.following {
+                    self.read_log();
+                    self.following = false;
+                }
                 if let Ok(file) = File::create("/tmp/icicle-term.log") {
                     let output = gio::WriteOutputStream::new(file);
                     if let Err(e) = self.terminal.write_contents_sync(
@@ -226,23 +304,7 @@ impl SimpleComponent for InstallModel {
                     }
                     let _ = output.flush(gio::Cancellable::NONE);
                 }
-                if self.installing {
-                    if status == 0 {
-                        debug!("Installation Success!");
-                        let _ = sender.output(AppMsg::FinishInstall);
-                    } else {
-                        debug!("Installation Failed!");
-                        let _ = sender.output(AppMsg::Error);
-                    }
-                } else {
-                    if status == 0 {
-                        debug!("Post install command success!");
-                        let _ = sender.output(AppMsg::RunNextCommand);
-                    } else {
-                        debug!("Post install command failed!");
-                        let _ = sender.output(AppMsg::Error);
-                    }
-                }
+                let _ = sender.output(AppMsg::StepFinished(status));
             }
             InstallMsg::SetLocale(locale) => {
                 self.locale = locale;
@@ -256,6 +318,102 @@ impl SimpleComponent for InstallModel {
     }
 }
 
+impl InstallModel {
+    /// Feeds the lines nixos-install printed since the last call to the progress parser
+    fn read_log(&mut self) {
+        let Ok(mut file) = File::open(INSTALL_LOG_PATH) else {
+            return;
+        };
+        // Retrying a step truncates the log, so start reading it over
+        if file
+            .metadata()
+            .is_ok_and(|metadata| metadata.len() < self.logoffset)
+        {
+            self.logoffset = 0;
+            self.logline.clear();
+        }
+        let mut buf = vec![];
+        if file.seek(SeekFrom::Start(self.logoffset)).is_err()
+            || file.read_to_end(&mut buf).is_err()
+        {
+            return;
+        }
+        self.logoffset += buf.len() as u64;
+        self.logline.push_str(&String::from_utf8_lossy(&buf));
+        if let Some((lines, rest)) = self.logline.rsplit_once('\n') {
+            let rest = rest.to_string();
+            for line in lines.lines() {
+                self.progress.parse_line(line);
+            }
+            self.logline = rest;
+        }
+    }
+}
+
+fn progress_label(progress: &InstallProgress) -> String {
+    let phase = match &progress.phase {
+        Phase::Partitioning => gettext("Partitioning"),
+        Phase::Configuring => gettext("Generating configuration"),
+        Phase::Evaluating => gettext("Evaluating configuration"),
+        Phase::Downloading => i18n_f(
+            // Translators: Do NOT translate the '{}'
+            "Downloading {} of {}",
+            &[
+                &progress.fetched.to_string(),
+                &progress.fetch_total.to_string(),
+            ],
+        ),
+        Phase::Building => i18n_f(
+            // Translators: Do NOT translate the '{}'
+            "Building {} of {}",
+            &[
+                &progress.built.to_string(),
+                &progress.build_total.to_string(),
+            ],
+        ),
+        Phase::Bootloader => gettext("Installing bootloader"),
+        Phase::Finishing => gettext("Finishing installation"),
+        Phase::PostInstall {
+            current,
+            total,
+            label: Some(label),
+        } => i18n_f(
+            // Translators: Do NOT translate the '{}'
+            "{} ({} of {})",
+            &[
+                &gettext(label),
+                &(current + 1).to_string(),
+                &total.to_string(),
+            ],
+        ),
+        Phase::PostInstall { current, total, .. } => i18n_f(
+            // Translators: Do NOT translate the '{}'
+            "Running post install command {} of {}",
+            &[&(current + 1).to_string(), &total.to_string()],
+        ),
+    };
+    match progress.eta() {
+        Some(eta) if eta.as_secs() < 60 => {
+            format!("{} – {}", phase, gettext("Less than a minute remaining"))
+        }
+        Some(eta) => {
+            let minutes = (eta.as_secs() + 59) / 60;
+            format!(
+                "{} – {}",
+                phase,
+                ni18n_f(
+                    // Translators: Do NOT translate the '{}'
+                    "About {} minute remaining",
+                    "About {} minutes remaining",
+                    minutes as u32,
+                    &[&minutes.to_string()],
+                )
+            )
+        }
+        None => phase,
+    }
+}
+
 #[derive(Debug)]
 #[tracker::track]
 pub struct InstallSlide {
@@ -1,8 +1,8 @@
-use crate::ui::window::AppMsg;
+use crate::ui::window::{AppMsg, KeyboardConfig, LayoutSwitch};
 use adw::prelude::*;
 use gettextrs::gettext;
 use gnome_desktop::{self, XkbInfo, XkbInfoExt};
-use log::trace;
+use log::{debug, trace};
 use relm4::*;
 use std::process::Command;
 
@@ -18,6 +18,13 @@ pub struct KeyboardModel {
     selected: Option<String>,
     expanders: Vec<adw::ExpanderRow>,
     shortkbdbox: gtk::ListBox,
+    /// Layouts after the selected one, in the order they are switched through
+    extras: Vec<String>,
+    extrabox: gtk::ListBox,
+    switch: LayoutSwitch,
+    /// Layouts that can be added, in the order of the add row
+    addlayouts: Vec<String>,
+    addselected: u32,
     xkb: XkbInfo,
 }
 
@@ -27,6 +34,107 @@ pub enum KeyboardMsg {
     SetSelected(Option<String>),
     SetCountry(String, String),
     CheckSelected,
+    SetAddSelected(u32),
+    AddLayout,
+    RemoveLayout(usize),
+    MoveLayoutUp(usize),
+    SetSwitch(LayoutSwitch),
+}
+
+impl KeyboardModel {
+    /// All layouts, starting with the selected one
+    fn config(&self) -> Option<KeyboardConfig> {
+        let selected = self.selected.as_ref()?;
+        let mut layouts = vec![selected.to_string()];
+        layouts.extend(self.extras.iter().filter(|x| *x != selected).cloned());
+        Some(KeyboardConfig {
+            layouts,
+            switch: self.switch,
+        })
+    }
+
+    /// Sends the layouts to the window and uses them in the live session,
+    /// so they can be tried in the test entry
+    fn apply(&self, sender: &ComponentSender<Self>) {
+        let Some(config) = self.config() else {
+            return;
+        };
+        let sources = config
+            .layouts
+            .iter()
+            .map(|layout| format!("('xkb','{}')", layout))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let _ = Command::new("gsettings")
+            .arg("set")
+            .arg("org.gnome.desktop.input-sources")
+            .arg("sources")
+            .arg(format!("[{}]", sources))
+            .spawn();
+        let options = config.xkb_options();
+        let _ = Command::new("gsettings")
+            .arg("set")
+            .arg("org.gnome.desktop.input-sources")
+            .arg("xkb-options")
+            .arg(match options {
+                Some(option) => format!("['{}']", option),
+                None => "[]".to_string(),
+            })
+            .spawn();
+        let (layouts, variants) = config.xkb_layouts();
+        let mut setxkbmap = Command::new("setxkbmap");
+        setxkbmap
+            .arg("-layout")
+            .arg(layouts)
+            .arg("-variant")
+            .arg(variants)
+            // An empty option clears the previous ones
+            .arg("-option")
+            .arg("");
+        if let Some(option) = options {
+            setxkbmap.arg("-option").arg(option);
+        }
+        let _ = setxkbmap.spawn();
+        let _ = sender.output(AppMsg::SetKeyboardConfig(Some(config)));
+    }
+
+    fn refresh_extras(&self, sender: &ComponentSender<Self>) {
+        self.extrabox.remove_all();
+        for (i, layout) in self.extras.iter().enumerate() {
+            let name = self
+                .xkb
+                .layout_info(layout)
+                .and_then(|x| x.0)
+                .map(|x| x.to_string())
+                .unwrap_or_else(|| layout.to_string());
+            view! {
+                row = adw::ActionRow {
+                    set_title: &name,
+                    set_subtitle: layout,
+                    add_suffix = &gtk::Button {
+                        set_icon_name: "go-up-symbolic",
+                        set_valign: gtk::Align::Center,
+                        add_css_class: "flat",
+                        set_tooltip_text: Some(&gettext("Move Up")),
+                        set_sensitive: i > 0,
+                        connect_clicked[sender] => move |_| {
+                            sender.input(KeyboardMsg::MoveLayoutUp(i));
+                        }
+                    },
+                    add_suffix = &gtk::Button {
+                        set_icon_name: "list-remove-symbolic",
+                        set_valign: gtk::Align::Center,
+                        add_css_class: "flat",
+                        set_tooltip_text: Some(&gettext("Remove Layout")),
+                        connect_clicked[sender] => move |_| {
+                            sender.input(KeyboardMsg::RemoveLayout(i));
+                        }
+                    }
+                }
+            }
+            self.extrabox.append(&row);
+        }
+    }
 }
 
 #[relm4::component(pub)]
@@ -87,8 +195,56 @@ impl SimpleComponent for KeyboardModel {
                             sender.input(KeyboardMsg::ToggleShowall);
                             sender.input(KeyboardMsg::SetSelected(None));
                         }
+                    },
+                    gtk::Label {
+                        #[watch]
+                        set_label: &gettext("Additional Layouts"),
+                        add_css_class: "title-4",
+                        set_halign: gtk::Align::Start,
+                    },
+                    #[local_ref]
+                    extrabox -> gtk::ListBox {
+                        add_css_class: "boxed-list",
+                        set_selection_mode: gtk::SelectionMode::None,
+                        #[watch]
+                        set_visible: !model.extras.is_empty(),
+                    },
+                    gtk::ListBox {
+                        add_css_class: "boxed-list",
+                        set_selection_mode: gtk::SelectionMode::None,
+                        adw::ComboRow {
+                            #[watch]
+                            set_title: &gettext("Add a Layout"),
+                            set_model: Some(&addmodel),
+                            connect_selected_notify[sender] => move |row| {
+                                sender.input(KeyboardMsg::SetAddSelected(row.selected()));
+                            },
+                            add_suffix = &gtk::Button {
+                                set_icon_name: "list-add-symbolic",
+                                set_valign: gtk::Align::Center,
+                                add_css_class: "flat",
+                                #[watch]
+                                set_tooltip_text: Some(&gettext("Add Layout")),
+                                connect_clicked[sender] => move |_| {
+                                    sender.input(KeyboardMsg::AddLayout);
+                                }
+                            }
+                        },
+                        adw::ComboRow {
+                            #[watch]
+                            set_title: &gettext("Switch Layouts"),
+                            #[watch]
+                            set_visible: !model.extras.is_empty(),
+                            set_model: Some(&switchmodel),
+                            connect_selected_notify[sender] => move |row| {
+                                let switch = LayoutSwitch::ALL
+                                    .get(row.selected() as usize)
+                                    .copied()
+                                    .unwrap_or_default();
+                                sender.input(KeyboardMsg::SetSwitch(switch));
+                            }
+                        }
                     }
-
                 }
             }
         }
@@ -99,7 +255,7 @@ impl SimpleComponent for KeyboardModel {
         root: &Self::Root,
         sender: ComponentSender<Self>,
     ) -> ComponentParts<Self> {
-        println!("Keyboard init");
+        debug!("Keyboard init");
         let xkb = XkbInfo::new();
         let layouts = xkb.all_layouts();
 
@@ -131,9 +287,33 @@ impl SimpleComponent for KeyboardModel {
             selectiongroup: gtk::CheckButton::new(),
             expanders: vec![],
             shortkbdbox: gtk::ListBox::new(),
+            extras: vec![],
+            extrabox: gtk::ListBox::new(),
+            switch: LayoutSwitch::default(),
+            addlayouts: vec![],
+            addselected: 0,
             tracker: 0,
         };
 
+        let mut addvec = model
+            .layouts
+            .iter()
+            .map(|(layout, (name, _, _, _))| (layout.to_string(), name.to_string()))
+            .collect::<Vec<_>>();
+        addvec.sort_by(|a, b| a.1.cmp(&b.1));
+        let addmodel = gtk::StringList::new(&[]);
+        for (layout, name) in addvec {
+            addmodel.append(&name);
+            model.addlayouts.push(layout);
+        }
+        let switches = LayoutSwitch::ALL
+            .iter()
+            .map(|switch| switch.keys())
+            .collect::<Vec<_>>();
+        let switchmodel =
+            gtk::StringList::new(&switches.iter().map(|x| x.as_str()).collect::<Vec<_>>());
+        let extrabox = model.extrabox.clone();
+
         let kbdbox = gtk::ListBox::new();
         let shortkbdbox = gtk::ListBox::new();
 
@@ -144,7 +324,7 @@ impl SimpleComponent for KeyboardModel {
             .filter(|x| x != &"custom")
             .collect::<Vec<_>>();
         countries.dedup();
-        println!("Pre sort");
+        debug!("Pre sort");
         countries.sort_by(|a, b| {
             let aname = gnome_desktop::country_from_code(&a.to_uppercase(), None)
                 .map(|x| x.to_string())
@@ -186,7 +366,7 @@ impl SimpleComponent for KeyboardModel {
                 });
             aname.cmp(&bname)
         });
-        println!("Post sort");
+        debug!("Post sort");
 
         for country in &countries {
             let possible_country = model
@@ -289,29 +469,15 @@ impl SimpleComponent for KeyboardModel {
                     let _ = sender.output(AppMsg::SetCanGoForward(false));
                 } else {
                     let _ = sender.output(AppMsg::SetCanGoForward(true));
-                    let _ = sender.output(AppMsg::SetKeyboardConfig(layout.clone()));
                 }
                 self.selected = layout;
                 if let Some(selected) = &self.selected {
-                    let _ = Command::new("gsettings")
-                        .arg("set")
-                        .arg("org.gnome.desktop.input-sources")
-                        .arg("sources")
-                        .arg(&format!("[('xkb','{}')]", selected))
-                        .spawn();
-                    if let (Some(layout), Some(variant)) =
-                        (selected.split('+').next(), selected.split('+').nth(1))
-                    {
-                        let _ = Command::new("setxkbmap")
-                            .arg("-layout")
-                            .arg(layout)
-                            .arg("-variant")
-                            .arg(variant)
-                            .spawn();
-                    } else {
-                        let _ = Command::new("setxkbmap").arg(selected).spawn();
+                    if self.extras.contains(selected) {
+                        self.extras.retain(|x| x != selected);
+                        self.refresh_extras(&sender);
                     }
                 }
+                self.apply(&sender);
             }
             KeyboardMsg::CheckSelected => {
                 trace!("KeyboardMsg::CheckSelected {}", self.selected.is_some());
@@ -330,6 +496,36 @@ impl SimpleComponent for KeyboardModel {
                 }
                 self.showall = !self.showall;
             }
+            KeyboardMsg::SetAddSelected(i) => {
+                self.addselected = i;
+            }
+            KeyboardMsg::AddLayout => {
+                if let Some(layout) = self.addlayouts.get(self.addselected as usize) {
+                    if self.selected.as_ref() != Some(layout) && !self.extras.contains(layout) {
+                        self.extras.push(layout.to_string());
+                        self.refresh_extras(&sender);
+                        self.apply(&sender);
+                    }
+                }
+            }
+            KeyboardMsg::RemoveLayout(i) => {
+                if i < self.extras.len() {
+                    self.extras.remove(i);
+                    self.refresh_extras(&sender);
+                    self.apply(&sender);
+                }
+            }
+            KeyboardMsg::MoveLayoutUp(i) => {
+                if i > 0 && i < self.extras.len() {
+                    self.extras.swap(i - 1, i);
+                    self.refresh_extras(&sender);
+                    self.apply(&sender);
+                }
+            }
+            KeyboardMsg::SetSwitch(switch) => {
+                self.switch = switch;
+                self.apply(&sender);
+            }
             KeyboardMsg::SetCountry(language, country) => {
                 let layouts = self.layouts.iter().filter_map(|(layout, (_name, lang, _country, _variant))| if lang == &language.to_lowercase() { Some(layout.to_string()) } else { None }).collect::<Vec<_>>();
                 let mut shortvec = layouts
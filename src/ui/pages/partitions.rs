@@ -36,13 +36,13 @@ pub enum PartitionMethod {
     Advanced,
 }
 
-#[derive(Serialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum PartitionSchema {
     FullDisk(String),
     Custom(HashMap<String, CustomPartition>),
 }
 
-#[derive(Serialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct CustomPartition {
     pub format: Option<String>,
     pub mountpoint: Option<String>,
@@ -1,5 +1,5 @@
 use super::partitions::{CustomPartition, PartitionSchema};
-use crate::ui::window::{AppMsg, UserConfig};
+use crate::ui::window::{AppMsg, KeyboardConfig, UserConfig};
 use adw::prelude::*;
 use gettextrs::gettext;
 use gnome_desktop::{self, XkbInfo, XkbInfoExt};
@@ -9,7 +9,7 @@ use relm4::{factory::*, *};
 #[tracker::track]
 pub struct SummaryModel {
     languageconfig: Option<String>,
-    keyboardconfig: Option<String>,
+    keyboardconfig: Option<KeyboardConfig>,
     timezoneconfig: Option<String>,
     #[tracker::no_eq]
     partitionconfig: Option<PartitionSchema>,
@@ -28,7 +28,7 @@ pub struct SummaryModel {
 pub enum SummaryMsg {
     SetConfig(
         Option<String>,
-        Option<String>,
+        Option<KeyboardConfig>,
         Option<String>,
         Option<PartitionSchema>,
         Box<Option<UserConfig>>,
@@ -77,7 +77,16 @@ impl SimpleComponent for SummaryModel {
                             #[watch]
                             set_title: model.prettykeyboard.as_ref().unwrap_or(&"None".to_string()),
                             #[watch]
-                            set_subtitle: model.keyboardconfig.as_ref().unwrap_or(&"None".to_string()),
+                            set_subtitle: &model.keyboardconfig.as_ref().map(|k| k.layouts.join(", ")).unwrap_or_else(|| "None".to_string()),
+                        },
+                        adw::ActionRow {
+                            set_activatable: false,
+                            #[watch]
+                            set_visible: model.keyboardconfig.as_ref().and_then(|k| k.xkb_options()).is_some(),
+                            #[watch]
+                            set_title: &gettext("Switch Layouts"),
+                            #[watch]
+                            set_subtitle: &model.keyboardconfig.as_ref().map(|k| k.switch.keys()).unwrap_or_default(),
                         },
                     },
                     adw::PreferencesGroup {
@@ -138,8 +147,8 @@ impl SimpleComponent for SummaryModel {
                                 set_valign: gtk::Align::Center,
                                 gtk::Label {
                                     #[watch]
-                                    set_markup: &if let Some(userconf) = &model.userconfig {
-                                        format!("<tt>{}</tt>", userconf.name)
+                                    set_markup: &if let Some(user) = model.userconfig.as_ref().and_then(|u| u.main_user()) {
+                                        format!("<tt>{}</tt>", user.name)
                                     } else {
                                         "<tt>N/A</tt>".to_string()
                                     },
@@ -151,13 +160,33 @@ impl SimpleComponent for SummaryModel {
                             set_activatable: false,
                             #[watch]
                             set_title: &gettext("Username"),
+                            add_suffix = &gtk::Button {
+                                set_halign: gtk::Align::Start,
+                                set_valign: gtk::Align::Center,
+                                gtk::Label {
+                                    #[watch]
+                                    set_markup: &if let Some(user) = model.userconfig.as_ref().and_then(|u| u.main_user()) {
+                                        format!("<tt>{}</tt>", user.username)
+                                    } else {
+                                        "<tt>N/A</tt>".to_string()
+                                    },
+                                },
+                                set_can_target: false,
+                            }
+                        },
+                        adw::ActionRow {
+                            set_activatable: false,
+                            #[watch]
+                            set_title: &gettext("Additional Users"),
+                            #[watch]
+                            set_visible: model.userconfig.as_ref().map(|u| u.users.len() > 1).unwrap_or(false),
                             add_suffix = &gtk::Button {
                                 set_halign: gtk::Align::Start,
                                 set_valign: gtk::Align::Center,
                                 gtk::Label {
                                     #[watch]
                                     set_markup: &if let Some(userconf) = &model.userconfig {
-                                        format!("<tt>{}</tt>", userconf.username)
+                                        format!("<tt>{}</tt>", userconf.users.iter().skip(1).map(|u| u.username.as_str()).collect::<Vec<_>>().join(", "))
                                     } else {
                                         "<tt>N/A</tt>".to_string()
                                     },
@@ -228,7 +257,9 @@ impl SimpleComponent for SummaryModel {
                 userconfig,
             ) => {
                 let debuguser = userconfig.clone().map(|mut user| {
-                    user.password = "*****".to_string();
+                    for account in &mut user.users {
+                        account.password = "*****".to_string();
+                    }
                     user.rootpassword = user.rootpassword.map(|_| "*****".to_string());
                     user
                 });
@@ -250,11 +281,17 @@ impl SimpleComponent for SummaryModel {
 
                 if let Some(keyboard) = self.keyboardconfig.as_ref() {
                     let xkb = XkbInfo::new();
-                    let layout = xkb
-                        .layout_info(keyboard)
-                        .and_then(|x| x.0)
-                        .map(|x| x.to_string());
-                    self.prettykeyboard = layout;
+                    let layouts = keyboard
+                        .layouts
+                        .iter()
+                        .map(|layout| {
+                            xkb.layout_info(layout)
+                                .and_then(|x| x.0)
+                                .map(|x| x.to_string())
+                                .unwrap_or_else(|| layout.to_string())
+                        })
+                        .collect::<Vec<_>>();
+                    self.prettykeyboard = Some(layouts.join(", "));
                 }
 
                 if let Some(PartitionSchema::Custom(partitions)) = &self.partitionconfig {
@@ -1,8 +1,17 @@
-use crate::ui::window::{AppMsg, UserConfig};
+use crate::{
+    config::SYSCONFDIR,
+    ui::window::{AppMsg, Shell, UserAccount, UserConfig},
+    utils::{
+        i18n::{i18n_f, ni18n_f},
+        parse::PasswordPolicy,
+        password, ssh, transliterate, validate,
+    },
+};
 use adw::prelude::*;
 use gettextrs::gettext;
-use log::{debug, trace};
-use relm4::*;
+use log::{debug, error, trace};
+use relm4::{factory::*, *};
+use std::{collections::HashSet, fs, path::PathBuf};
 
 #[tracker::track]
 pub struct UserModel {
@@ -13,6 +22,18 @@ pub struct UserModel {
     hostname: Option<String>,
     root_password: Option<String>,
     confirm_root_password: Option<String>,
+    #[tracker::no_eq]
+    policy: PasswordPolicy,
+    password_strength: u32,
+    password_warnings: Vec<String>,
+    override_policy: bool,
+    name_error: Option<String>,
+    username_error: Option<String>,
+    hostname_error: Option<String>,
+    groups_error: Option<String>,
+    /// Why no one could administrate the installed system
+    admin_error: Option<String>,
+    name_row: adw::EntryRow,
     username_row: adw::EntryRow,
     confirm_password_row: adw::PasswordEntryRow,
     confirm_root_password_row: adw::PasswordEntryRow,
@@ -20,11 +41,27 @@ pub struct UserModel {
     showhostname: bool,
     showrootpassword: bool,
     autologin: bool,
+    admin: bool,
+    groups: Vec<String>,
+    shell: Shell,
+    #[tracker::no_eq]
+    accounts: FactoryVecDeque<AccountRow>,
+    authorized_keys: Vec<String>,
+    keys_error: Option<String>,
+    keys_buffer: gtk::TextBuffer,
+    #[tracker::no_eq]
+    file_dialog: Option<gtk::FileChooserNative>,
+    avatar: Option<String>,
+    #[tracker::no_eq]
+    avatar_texture: Option<gtk::gdk::Texture>,
+    #[tracker::no_eq]
+    bundled_avatars: Vec<String>,
+    openssh: bool,
 }
 
 #[derive(Debug)]
 pub enum UserMsg {
-    SetConfig(bool, bool, String),
+    SetConfig(bool, bool, String, PasswordPolicy),
     NameChanged(String),
     UsernameChanged(String),
     PasswordChanged(String),
@@ -33,11 +70,40 @@ pub enum UserMsg {
     RootPasswordChanged(String),
     ConfirmRootPasswordChanged(String),
     SetPasswordStyle,
+    SetOverridePolicy(bool),
     SetRootPasswordStyle,
     SetAutoLogin(bool),
+    SetAdmin(bool),
+    GroupsChanged(Vec<String>),
+    SetShell(Shell),
+    AddAccount,
+    RemoveAccount(DynamicIndex),
+    KeysChanged(String),
+    ImportKeys,
+    ImportFailed(String),
+    SelectAvatarFile,
+    SetAvatar(Option<String>),
+    SetOpenssh(bool),
     CheckSelected,
 }
 
+impl UserModel {
+    /// Updates the strength of the password and how it breaks the policy
+    fn check_password(&mut self) {
+        let password = self.password.as_deref().unwrap_or_default();
+        self.password_strength = password::strength(password);
+        self.password_warnings = if password.is_empty() {
+            vec![]
+        } else {
+            password::policy_violations(
+                password,
+                self.username.as_deref().unwrap_or_default(),
+                &self.policy,
+            )
+        };
+    }
+}
+
 #[relm4::component(pub)]
 impl SimpleComponent for UserModel {
     type Init = ();
@@ -54,8 +120,63 @@ impl SimpleComponent for UserModel {
                     set_orientation: gtk::Orientation::Vertical,
                     set_spacing: 20,
                     set_margin_all: 20,
-                    adw::Avatar {
-                        set_size: 144,
+                    gtk::MenuButton {
+                        set_halign: gtk::Align::Center,
+                        add_css_class: "flat",
+                        add_css_class: "circular",
+                        #[watch]
+                        set_tooltip_text: Some(&gettext("Choose an Avatar")),
+                        #[wrap(Some)]
+                        set_child = &adw::Avatar {
+                            set_size: 144,
+                            set_show_initials: true,
+                            #[watch]
+                            set_text: model.name.as_deref(),
+                            #[watch]
+                            set_custom_image: model.avatar_texture.as_ref(),
+                        },
+                        #[wrap(Some)]
+                        #[name(avatarpopover)]
+                        set_popover = &gtk::Popover {
+                            gtk::Box {
+                                set_orientation: gtk::Orientation::Vertical,
+                                set_spacing: 6,
+                                #[local_ref]
+                                avatar_grid -> gtk::FlowBox {
+                                    #[watch]
+                                    set_visible: !model.bundled_avatars.is_empty(),
+                                    set_selection_mode: gtk::SelectionMode::None,
+                                    set_max_children_per_line: 5,
+                                    set_homogeneous: true,
+                                    connect_child_activated[sender, avatarpopover, avatars = model.bundled_avatars.clone()] => move |_, child| {
+                                        if let Some(avatar) = avatars.get(child.index() as usize) {
+                                            sender.input(UserMsg::SetAvatar(Some(avatar.to_string())));
+                                        }
+                                        avatarpopover.popdown();
+                                    }
+                                },
+                                gtk::Button {
+                                    add_css_class: "flat",
+                                    #[watch]
+                                    set_label: &gettext("Select a File…"),
+                                    connect_clicked[sender, avatarpopover] => move |_| {
+                                        avatarpopover.popdown();
+                                        sender.input(UserMsg::SelectAvatarFile);
+                                    }
+                                },
+                                gtk::Button {
+                                    add_css_class: "flat",
+                                    #[watch]
+                                    set_label: &gettext("Remove Avatar"),
+                                    #[watch]
+                                    set_visible: model.avatar.is_some(),
+                                    connect_clicked[sender, avatarpopover] => move |_| {
+                                        avatarpopover.popdown();
+                                        sender.input(UserMsg::SetAvatar(None));
+                                    }
+                                }
+                            }
+                        }
                     },
                     gtk::Label {
                         #[watch]
@@ -65,9 +186,12 @@ impl SimpleComponent for UserModel {
                     gtk::ListBox {
                         add_css_class: "boxed-list",
                         set_selection_mode: gtk::SelectionMode::None,
-                        adw::EntryRow {
+                        #[local_ref]
+                        name_row -> adw::EntryRow {
                             #[watch]
                             set_title: &gettext("Name"),
+                            #[watch]
+                            show_error: (model.name_error.as_deref(), None),
                             connect_changed[sender] => move |entry| {
                                 sender.input(UserMsg::NameChanged(entry.text().to_string()));
                             }
@@ -76,31 +200,8 @@ impl SimpleComponent for UserModel {
                         username_row -> adw::EntryRow {
                             #[watch]
                             set_title: &gettext("Username"),
-                            connect_text_notify => move |entry| {
-                                let mut corrected = String::new();
-                                for c in entry.text().chars() {
-                                    if c.is_ascii_lowercase() || c.is_ascii_digit() {
-                                        corrected.push(c);
-                                    }
-                                }
-
-                                while let Some(first) = corrected.chars().next() {
-                                    if first.is_ascii_digit() {
-                                        if let Some(c) = corrected.get(1..) {
-                                            corrected = c.to_string();
-                                        } else {
-                                            corrected = String::new();
-                                            break;
-                                        }
-                                    } else {
-                                        break;
-                                    }
-                                }
-
-                                if entry.text() != corrected {
-                                    entry.set_text(&corrected);
-                                }
-                            },
+                            #[watch]
+                            show_error: (model.username_error.as_deref(), None),
                             connect_changed[sender] => move |entry| {
                                 sender.input(UserMsg::UsernameChanged(entry.text().to_string()));
                             },
@@ -120,6 +221,89 @@ impl SimpleComponent for UserModel {
                                 sender.input(UserMsg::ConfirmPasswordChanged(entry.text().to_string()));
                             }
                         },
+                        gtk::ListBoxRow {
+                            set_activatable: false,
+                            #[watch]
+                            set_visible: model.password.is_some(),
+                            gtk::Box {
+                                set_orientation: gtk::Orientation::Vertical,
+                                set_spacing: 6,
+                                set_margin_all: 12,
+                                gtk::LevelBar {
+                                    set_mode: gtk::LevelBarMode::Discrete,
+                                    set_min_value: 0.0,
+                                    set_max_value: 4.0,
+                                    add_offset_value: (gtk::LEVEL_BAR_OFFSET_LOW, 1.0),
+                                    add_offset_value: (gtk::LEVEL_BAR_OFFSET_HIGH, 3.0),
+                                    add_offset_value: (gtk::LEVEL_BAR_OFFSET_FULL, 4.0),
+                                    #[watch]
+                                    set_value: model.password_strength as f64,
+                                },
+                                gtk::Label {
+                                    add_css_class: "caption",
+                                    set_xalign: 0.0,
+                                    #[watch]
+                                    set_label: &strength_label(model.password_strength),
+                                },
+                                gtk::Label {
+                                    add_css_class: "caption",
+                                    add_css_class: "warning",
+                                    set_wrap: true,
+                                    set_xalign: 0.0,
+                                    #[watch]
+                                    set_visible: !model.password_warnings.is_empty(),
+                                    #[watch]
+                                    set_label: &model.password_warnings.join("\n"),
+                                },
+                                gtk::CheckButton {
+                                    #[watch]
+                                    set_label: Some(&gettext("Use this password anyway")),
+                                    #[watch]
+                                    set_visible: !model.password_warnings.is_empty() && !model.policy.enforce,
+                                    connect_toggled[sender] => move |check| {
+                                        sender.input(UserMsg::SetOverridePolicy(check.is_active()));
+                                    }
+                                }
+                            }
+                        },
+                        adw::ActionRow {
+                            #[watch]
+                            set_title: &gettext("Administrator"),
+                            set_activatable: true,
+                            connect_activated[adminswitch] => move |_| {
+                                adminswitch.activate();
+                            },
+                            #[name(adminswitch)]
+                            add_suffix = &gtk::Switch {
+                                set_valign: gtk::Align::Center,
+                                set_active: true,
+                                connect_state_set[sender] => move |_, state| {
+                                    sender.input(UserMsg::SetAdmin(state));
+                                    glib::Propagation::Proceed
+                                }
+                            }
+                        },
+                        adw::EntryRow {
+                            #[watch]
+                            set_title: &gettext("Extra Groups"),
+                            #[watch]
+                            show_error: (
+                                model.groups_error.as_deref(),
+                                Some(gettext("Separate groups with commas or spaces").as_str()),
+                            ),
+                            connect_text_notify => correct_groups,
+                            connect_changed[sender] => move |entry| {
+                                sender.input(UserMsg::GroupsChanged(parse_groups(&entry.text())));
+                            }
+                        },
+                        adw::ComboRow {
+                            #[watch]
+                            set_title: &gettext("Login Shell"),
+                            set_model: Some(&shell_model()),
+                            connect_selected_notify[sender] => move |row| {
+                                sender.input(UserMsg::SetShell(selected_shell(row)));
+                            }
+                        },
                         adw::ActionRow {
                             #[watch]
                             set_title: &gettext("Log in automatically"),
@@ -137,6 +321,31 @@ impl SimpleComponent for UserModel {
                             }
                         }
                     },
+                    #[local_ref]
+                    accounts_box -> gtk::ListBox {
+                        #[watch]
+                        set_visible: !model.accounts.is_empty(),
+                        add_css_class: "boxed-list",
+                        set_selection_mode: gtk::SelectionMode::None,
+                    },
+                    gtk::Label {
+                        add_css_class: "error",
+                        set_wrap: true,
+                        set_xalign: 0.0,
+                        #[watch]
+                        set_visible: model.admin_error.is_some(),
+                        #[watch]
+                        set_label: model.admin_error.as_deref().unwrap_or_default(),
+                    },
+                    gtk::Button {
+                        add_css_class: "pill",
+                        set_halign: gtk::Align::Center,
+                        #[watch]
+                        set_label: &gettext("Add User"),
+                        connect_clicked[sender] => move |_| {
+                            sender.input(UserMsg::AddAccount);
+                        }
+                    },
                     gtk::ListBox {
                         #[watch]
                         set_visible: model.showhostname,
@@ -146,21 +355,11 @@ impl SimpleComponent for UserModel {
                         hostnamerow -> adw::EntryRow {
                             #[watch]
                             set_title: &gettext("Hostname"),
+                            #[watch]
+                            show_error: (model.hostname_error.as_deref(), None),
                             connect_changed[sender] => move |entry| {
                                 sender.input(UserMsg::HostnameChanged(entry.text().to_string()));
                             },
-                            connect_text_notify => move |entry| {
-                                let mut corrected = String::new();
-                                for c in entry.text().chars() {
-                                    if c.is_ascii_alphanumeric() || c.is_ascii_digit() {
-                                        corrected.push(c);
-                                    }
-                                }
-
-                                if entry.text() != corrected {
-                                    entry.set_text(&corrected);
-                                }
-                            }
                         }
                     },
                     gtk::ListBox {
@@ -183,6 +382,62 @@ impl SimpleComponent for UserModel {
                                 sender.input(UserMsg::ConfirmRootPasswordChanged(entry.text().to_string()));
                             }
                         }
+                    },
+                    gtk::ListBox {
+                        add_css_class: "boxed-list",
+                        set_selection_mode: gtk::SelectionMode::None,
+                        adw::ActionRow {
+                            #[watch]
+                            set_title: &gettext("SSH Authorized Keys"),
+                            #[watch]
+                            set_subtitle: &if let Some(e) = &model.keys_error {
+                                e.to_string()
+                            } else if model.authorized_keys.is_empty() {
+                                gettext("Paste public keys, one per line")
+                            } else {
+                                ni18n_f("{} key", "{} keys", model.authorized_keys.len() as u32, &[&model.authorized_keys.len().to_string()])
+                            },
+                            add_suffix = &gtk::Button {
+                                set_valign: gtk::Align::Center,
+                                add_css_class: "flat",
+                                set_icon_name: "document-open-symbolic",
+                                #[watch]
+                                set_tooltip_text: Some(&gettext("Import from File")),
+                                connect_clicked[sender] => move |_| {
+                                    sender.input(UserMsg::ImportKeys);
+                                }
+                            }
+                        },
+                        gtk::ListBoxRow {
+                            set_activatable: false,
+                            gtk::TextView {
+                                set_buffer: Some(&model.keys_buffer),
+                                set_monospace: true,
+                                set_wrap_mode: gtk::WrapMode::Char,
+                                set_height_request: 80,
+                                set_margin_all: 12,
+                            }
+                        },
+                        adw::ActionRow {
+                            #[watch]
+                            set_title: &gettext("Enable SSH server"),
+                            #[watch]
+                            set_subtitle: &gettext("Only logins with SSH keys are allowed"),
+                            #[watch]
+                            set_sensitive: !model.authorized_keys.is_empty(),
+                            set_activatable: true,
+                            connect_activated[sshswitch] => move |_| {
+                                sshswitch.activate();
+                            },
+                            #[name(sshswitch)]
+                            add_suffix = &gtk::Switch {
+                                set_valign: gtk::Align::Center,
+                                connect_state_set[sender] => move |_, state| {
+                                    sender.input(UserMsg::SetOpenssh(state));
+                                    glib::Propagation::Proceed
+                                }
+                            }
+                        }
                     }
                 }
             }
@@ -202,6 +457,16 @@ impl SimpleComponent for UserModel {
             hostname: None,
             root_password: None,
             confirm_root_password: None,
+            policy: PasswordPolicy::default(),
+            password_strength: 0,
+            password_warnings: vec![],
+            override_policy: false,
+            name_error: None,
+            username_error: None,
+            hostname_error: None,
+            groups_error: None,
+            admin_error: None,
+            name_row: adw::EntryRow::new(),
             username_row: adw::EntryRow::new(),
             hostnamerow: adw::EntryRow::new(),
             confirm_password_row: adw::PasswordEntryRow::new(),
@@ -209,9 +474,44 @@ impl SimpleComponent for UserModel {
             showhostname: false,
             showrootpassword: false,
             autologin: false,
+            admin: true,
+            groups: vec![],
+            shell: Shell::default(),
+            accounts: FactoryVecDeque::builder().launch_default().forward(
+                sender.input_sender(),
+                |msg| match msg {
+                    AccountRowOutput::Changed => UserMsg::CheckSelected,
+                    AccountRowOutput::Remove(index) => UserMsg::RemoveAccount(index),
+                },
+            ),
+            authorized_keys: vec![],
+            keys_error: None,
+            keys_buffer: gtk::TextBuffer::new(None),
+            file_dialog: None,
+            avatar: None,
+            avatar_texture: None,
+            bundled_avatars: bundled_avatars(),
+            openssh: false,
             tracker: 0,
         };
+        let keys_sender = sender.clone();
+        model.keys_buffer.connect_changed(move |buffer| {
+            let text = buffer.text(&buffer.start_iter(), &buffer.end_iter(), false);
+            keys_sender.input(UserMsg::KeysChanged(text.to_string()));
+        });
+        let avatar_grid = gtk::FlowBox::new();
+        for avatar in &model.bundled_avatars {
+            let preview = adw::Avatar::new(64, None, false);
+            match gtk::gdk::Texture::from_filename(avatar) {
+                Ok(texture) => preview.set_custom_image(Some(&texture)),
+                Err(e) => error!("Failed to load avatar {}: {}", avatar, e),
+            }
+            avatar_grid.append(&preview);
+        }
+        let avatar_grid = &avatar_grid;
+        let name_row = &model.name_row;
         let username_row = &model.username_row;
+        let accounts_box = model.accounts.widget();
         let confirm_password_row = &model.confirm_password_row;
         let confirm_root_password_row = &model.confirm_root_password_row;
         let hostnamerow = &model.hostnamerow;
@@ -222,45 +522,39 @@ impl SimpleComponent for UserModel {
     fn update(&mut self, msg: Self::Input, sender: ComponentSender<Self>) {
         self.reset();
         match msg {
-            UserMsg::SetConfig(root, showhostname, hostname) => {
+            UserMsg::SetConfig(root, showhostname, hostname, policy) => {
+                self.policy = policy;
+                self.check_password();
                 self.showrootpassword = root;
                 self.showhostname = showhostname;
                 self.hostname = Some(hostname.to_string());
+                self.hostname_error = check(&hostname, validate::check_hostname);
                 self.hostnamerow.set_text(&hostname);
             }
             UserMsg::NameChanged(name) => {
-                // Replace any non a-z characters with an ""
-                let suggested_username = name
-                    .to_ascii_lowercase()
-                    .replace(' ', "")
-                    .chars()
-                    .filter(|c| c.is_ascii_alphanumeric())
-                    .collect::<String>();
+                let suggested_username = suggest_username(&name);
 
                 if let Some(username) = &self.username {
                     if let Some(oldname) = &self.name {
-                        if username.eq(&oldname
-                            .to_ascii_lowercase()
-                            .replace(' ', "")
-                            .chars()
-                            .filter(|c| c.is_ascii_alphanumeric())
-                            .collect::<String>())
-                        {
+                        if username.eq(&suggest_username(oldname)) {
                             self.username_row.set_text(&suggested_username);
                         }
                     }
                 } else {
                     self.username_row.set_text(&suggested_username);
                 }
+                self.name_error = check(&name, validate::check_fullname);
                 self.name = if name.is_empty() { None } else { Some(name) };
                 sender.input(UserMsg::CheckSelected);
             }
             UserMsg::UsernameChanged(username) => {
+                self.username_error = check(&username, validate::check_username);
                 self.username = if username.is_empty() {
                     None
                 } else {
                     Some(username)
                 };
+                self.check_password();
                 sender.input(UserMsg::CheckSelected);
             }
             UserMsg::PasswordChanged(password) => {
@@ -269,6 +563,7 @@ impl SimpleComponent for UserModel {
                 } else {
                     Some(password)
                 };
+                self.check_password();
                 sender.input(UserMsg::SetPasswordStyle);
             }
             UserMsg::ConfirmPasswordChanged(confirm_password) => {
@@ -294,6 +589,10 @@ impl SimpleComponent for UserModel {
                 }
                 sender.input(UserMsg::CheckSelected);
             }
+            UserMsg::SetOverridePolicy(override_policy) => {
+                self.override_policy = override_policy;
+                sender.input(UserMsg::CheckSelected);
+            }
             UserMsg::HostnameChanged(hostname) => {
                 if let Some(current) = &self.hostname {
                     if &hostname == current {
@@ -301,6 +600,7 @@ impl SimpleComponent for UserModel {
                         return;
                     }
                 }
+                self.hostname_error = check(&hostname, validate::check_hostname);
 
                 self.hostname = if hostname.is_empty() {
                     None
@@ -344,13 +644,128 @@ impl SimpleComponent for UserModel {
                 self.autologin = autologin;
                 sender.input(UserMsg::CheckSelected);
             }
+            UserMsg::SetAdmin(admin) => {
+                self.admin = admin;
+                sender.input(UserMsg::CheckSelected);
+            }
+            UserMsg::GroupsChanged(groups) => {
+                self.groups_error = check_groups(&groups);
+                self.groups = groups;
+                sender.input(UserMsg::CheckSelected);
+            }
+            UserMsg::SetShell(shell) => {
+                self.shell = shell;
+                sender.input(UserMsg::CheckSelected);
+            }
+            UserMsg::AddAccount => {
+                self.accounts.guard().push_back(self.policy.clone());
+                sender.input(UserMsg::CheckSelected);
+            }
+            UserMsg::RemoveAccount(index) => {
+                self.accounts.guard().remove(index.current_index());
+                sender.input(UserMsg::CheckSelected);
+            }
+            UserMsg::KeysChanged(text) => {
+                match ssh::parse_authorized_keys(&text) {
+                    Ok(keys) => {
+                        self.authorized_keys = keys;
+                        self.keys_error = None;
+                    }
+                    Err(e) => {
+                        self.authorized_keys = vec![];
+                        self.keys_error = Some(e.to_string());
+                    }
+                }
+                sender.input(UserMsg::CheckSelected);
+            }
+            UserMsg::ImportKeys => {
+                let buffer = self.keys_buffer.clone();
+                // Keys are usually brought along on removable media
+                self.file_dialog = Some(open_file(
+                    &gettext("Import SSH Keys"),
+                    "/run/media",
+                    None,
+                    move |path| match fs::read_to_string(&path) {
+                        Ok(keys) => append_keys(&buffer, &keys),
+                        Err(e) => {
+                            error!("Failed to read {}: {}", path.display(), e);
+                            sender.input(UserMsg::ImportFailed(e.to_string()));
+                        }
+                    },
+                ));
+            }
+            UserMsg::SelectAvatarFile => {
+                let filter = gtk::FileFilter::new();
+                filter.set_name(Some(&gettext("Images")));
+                filter.add_pixbuf_formats();
+                self.file_dialog = Some(open_file(
+                    &gettext("Select an Avatar"),
+                    "/run/media",
+                    Some(&filter),
+                    move |path| {
+                        sender.input(UserMsg::SetAvatar(Some(path.to_string_lossy().to_string())))
+                    },
+                ));
+            }
+            UserMsg::SetAvatar(avatar) => {
+                self.avatar_texture = match &avatar {
+                    Some(path) => match gtk::gdk::Texture::from_filename(path) {
+                        Ok(texture) => Some(texture),
+                        Err(e) => {
+                            error!("Failed to load avatar {}: {}", path, e);
+                            return;
+                        }
+                    },
+                    None => None,
+                };
+                self.avatar = avatar;
+                sender.input(UserMsg::CheckSelected);
+            }
+            UserMsg::ImportFailed(e) => {
+                self.keys_error = Some(e);
+            }
+            UserMsg::SetOpenssh(openssh) => {
+                self.openssh = openssh;
+                sender.input(UserMsg::CheckSelected);
+            }
             UserMsg::CheckSelected => {
+                let mut usernames = HashSet::new();
+                if let Some(username) = &self.username {
+                    usernames.insert(username.as_str());
+                }
+                let mut duplicates = vec![];
+                for account in self.accounts.iter() {
+                    duplicates.push(!usernames.insert(account.username.as_str()));
+                }
+                let accountsvalid = self.accounts.iter().all(|account| account.is_valid())
+                    && !duplicates.contains(&true);
+                let hasadmin = self.admin || self.accounts.iter().any(|account| account.admin);
+                self.admin_error = if hasadmin || self.root_password.is_some() {
+                    None
+                } else {
+                    Some(gettext(
+                        "At least one user must be an administrator when no root password is set",
+                    ))
+                };
+                for (i, duplicate) in duplicates.into_iter().enumerate() {
+                    self.accounts
+                        .send(i, AccountRowMsg::SetDuplicate(duplicate));
+                }
                 let cangoforward = self.name.is_some()
+                    && self.name_error.is_none()
                     && self.username.is_some()
+                    && self.username_error.is_none()
+                    && self.groups_error.is_none()
                     && self.password.is_some()
                     && self.confirm_password.is_some()
                     && self.password == self.confirm_password
+                    && (self.password_warnings.is_empty()
+                        || (self.override_policy && !self.policy.enforce))
+                    && accountsvalid
+                    && self.admin_error.is_none()
+                    && self.keys_error.is_none()
                     && self.hostname.is_some()
+                    && self.hostname_error.is_none()
                     && self.root_password == self.confirm_root_password;
                 trace!("UserMsg::CheckSelected {}", cangoforward);
 
@@ -368,13 +783,32 @@ impl SimpleComponent for UserModel {
                         &self.confirm_password,
                         &self.hostname,
                     ) {
-                        let _ = sender.output(AppMsg::SetUserConfig(Some(UserConfig {
+                        let mut users = vec![UserAccount {
                             name: name.to_string(),
                             username: username.to_string(),
                             password: password.to_string(),
+                            admin: self.admin,
+                            groups: self.groups.clone(),
+                            shell: self.shell,
+                            authorized_keys: self.authorized_keys.clone(),
+                            avatar: self.avatar.clone(),
+                        }];
+                        users.extend(self.accounts.iter().map(|account| UserAccount {
+                            name: account.name.to_string(),
+                            username: account.username.to_string(),
+                            password: account.password.to_string(),
+                            admin: account.admin,
+                            groups: account.groups.clone(),
+                            shell: account.shell,
+                            authorized_keys: vec![],
+                            avatar: None,
+                        }));
+                        let _ = sender.output(AppMsg::SetUserConfig(Some(UserConfig {
+                            users,
                             hostname: hostname.to_string(),
                             rootpassword: self.root_password.clone(),
                             autologin: self.autologin,
+                            openssh: self.openssh && !self.authorized_keys.is_empty(),
                         })));
                     }
                 }
@@ -384,3 +818,391 @@ impl SimpleComponent for UserModel {
         }
     }
 }
+
+/// Suggests a username from a full name written in ASCII, dropping
+/// anything that is not a letter or digit, and any leading digits
+fn suggest_username(name: &str) -> String {
+    transliterate::to_ascii(name)
+        .to_ascii_lowercase()
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .skip_while(|c| c.is_ascii_digit())
+        .take(validate::USERNAME_MAX)
+        .collect::<String>()
+}
+
+fn strength_label(strength: u32) -> String {
+    match strength {
+        0 => gettext("Very weak password"),
+        1 => gettext("Weak password"),
+        2 => gettext("Fair password"),
+        3 => gettext("Good password"),
+        _ => gettext("Strong password"),
+    }
+}
+
+/// Why a value was rejected. Empty values are only required, not explained.
+fn check(value: &str, validate: impl Fn(&str) -> anyhow::Result<()>) -> Option<String> {
+    if value.is_empty() {
+        None
+    } else {
+        validate(value).err().map(|e| e.to_string())
+    }
+}
+
+/// Why the first invalid group was rejected
+fn check_groups(groups: &[String]) -> Option<String> {
+    groups.iter().find_map(|group| {
+        check(group, validate::check_group)
+            // Translators: Do NOT translate the '{}'
+            .map(|e| i18n_f("Invalid group “{}”: {}", &[group, &e]))
+    })
+}
+
+/// Explains on an entry row itself why its value was rejected
+trait ShowError {
+    /// Marks the row as invalid with the error as its tooltip, or puts the
+    /// hint back once the value is valid
+    fn show_error(&self, error: Option<&str>, hint: Option<&str>);
+}
+
+impl<W: IsA<gtk::Widget>> ShowError for W {
+    fn show_error(&self, error: Option<&str>, hint: Option<&str>) {
+        if error.is_some() {
+            self.add_css_class("error");
+        } else {
+            self.remove_css_class("error");
+        }
+        self.set_tooltip_text(error.or(hint));
+    }
+}
+
+/// Removes characters that are not allowed in group names as they are typed,
+/// keeping separators
+fn correct_groups(entry: &adw::EntryRow) {
+    let corrected = entry
+        .text()
+        .chars()
+        .filter(|c| {
+            c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '_' | '-' | ',' | ' ')
+        })
+        .collect::<String>();
+
+    if entry.text() != corrected {
+        entry.set_text(&corrected);
+    }
+}
+
+fn parse_groups(text: &str) -> Vec<String> {
+    text.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|group| !group.is_empty())
+        .map(|group| group.to_string())
+        .collect()
+}
+
+/// Images shipped with the installer configuration to pick an avatar from
+fn bundled_avatars() -> Vec<String> {
+    let mut avatars = fs::read_dir(format!("{}/icicle/avatars", SYSCONFDIR))
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|path| path.is_file())
+                .map(|path| path.to_string_lossy().to_string())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    avatars.sort();
+    avatars
+}
+
+/// Shows a dialog to open a file, calling `on_file` with the chosen file.
+/// The returned dialog has to be kept alive until it is answered.
+fn open_file(
+    title: &str,
+    folder: &str,
+    filter: Option<&gtk::FileFilter>,
+    on_file: impl Fn(PathBuf) + 'static,
+) -> gtk::FileChooserNative {
+    let dialog = gtk::FileChooserNative::new(
+        Some(title),
+        None::<&gtk::Window>,
+        gtk::FileChooserAction::Open,
+        Some(&gettext("Open")),
+        Some(&gettext("Cancel")),
+    );
+    dialog.set_modal(true);
+    if let Some(filter) = filter {
+        dialog.add_filter(filter);
+    }
+    let _ = dialog.set_current_folder(Some(&gtk::gio::File::for_path(folder)));
+    dialog.connect_response(move |dialog, response| {
+        if response == gtk::ResponseType::Accept {
+            if let Some(path) = dialog.file().and_then(|f| f.path()) {
+                on_file(path);
+            }
+        }
+        dialog.destroy();
+    });
+    dialog.show();
+    dialog
+}
+
+/// Adds imported keys on a new line after any keys already entered
+fn append_keys(buffer: &gtk::TextBuffer, keys: &str) {
+    let mut end = buffer.end_iter();
+    let text = buffer.text(&buffer.start_iter(), &end, false);
+    if !text.is_empty() && !text.ends_with('\n') {
+        buffer.insert(&mut end, "\n");
+    }
+    buffer.insert(&mut end, keys.trim_end());
+}
+
+fn shell_model() -> gtk::StringList {
+    gtk::StringList::new(&["Bash", "Zsh", "Fish"])
+}
+
+fn selected_shell(row: &adw::ComboRow) -> Shell {
+    Shell::ALL
+        .get(row.selected() as usize)
+        .copied()
+        .unwrap_or_default()
+}
+
+fn set_confirm_style(password_row: &adw::PasswordEntryRow, confirm_row: &adw::PasswordEntryRow) {
+    if password_row.text() == confirm_row.text() {
+        confirm_row.remove_css_class("error");
+        if password_row.text().is_empty() {
+            confirm_row.remove_css_class("success");
+        } else {
+            confirm_row.add_css_class("success");
+        }
+    } else {
+        confirm_row.add_css_class("error");
+        confirm_row.remove_css_class("success");
+    }
+}
+
+/// An additional user account
+#[derive(Debug, Default)]
+pub struct AccountRow {
+    name: String,
+    username: String,
+    password: String,
+    confirm_password: String,
+    admin: bool,
+    groups: Vec<String>,
+    shell: Shell,
+    /// Whether another user has the same username
+    duplicate: bool,
+    policy: PasswordPolicy,
+    override_policy: bool,
+}
+
+impl AccountRow {
+    fn is_valid(&self) -> bool {
+        !self.name.is_empty()
+            && !self.username.is_empty()
+            && self.name_error().is_none()
+            && self.username_error().is_none()
+            && check_groups(&self.groups).is_none()
+            && !self.password.is_empty()
+            && self.password == self.confirm_password
+            && (self.password_warning().is_none() || (self.override_policy && !self.policy.enforce))
+    }
+
+    /// How the password breaks the password policy
+    fn password_warning(&self) -> Option<String> {
+        if self.password.is_empty() {
+            return None;
+        }
+        password::policy_violations(&self.password, &self.username, &self.policy)
+            .into_iter()
+            .next()
+    }
+
+    fn name_error(&self) -> Option<String> {
+        check(&self.name, validate::check_fullname)
+    }
+
+    /// Why the username was rejected, including when another user has it
+    fn username_error(&self) -> Option<String> {
+        check(&self.username, validate::check_username).or_else(|| {
+            self.duplicate
+                .then(|| gettext("Another user already has this username"))
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum AccountRowMsg {
+    NameChanged(String),
+    UsernameChanged(String),
+    PasswordChanged(String),
+    ConfirmPasswordChanged(String),
+    SetAdmin(bool),
+    GroupsChanged(Vec<String>),
+    SetShell(Shell),
+    SetOverridePolicy(bool),
+    SetDuplicate(bool),
+}
+
+#[derive(Debug)]
+pub enum AccountRowOutput {
+    Changed,
+    Remove(DynamicIndex),
+}
+
+#[relm4::factory(pub)]
+impl FactoryComponent for AccountRow {
+    type Init = PasswordPolicy;
+    type Input = AccountRowMsg;
+    type Output = AccountRowOutput;
+    type ParentWidget = gtk::ListBox;
+    type CommandOutput = ();
+
+    view! {
+        adw::ExpanderRow {
+            #[watch]
+            set_title: &if self.name.is_empty() {
+                gettext("New User")
+            } else {
+                self.name.to_string()
+            },
+            #[watch]
+            set_subtitle: &self
+                .password_warning()
+                .unwrap_or_else(|| self.username.to_string()),
+            set_expanded: true,
+            add_action = &gtk::Button {
+                set_valign: gtk::Align::Center,
+                add_css_class: "flat",
+                set_icon_name: "user-trash-symbolic",
+                set_tooltip_text: Some(&gettext("Remove User")),
+                connect_clicked[sender, index] => move |_| {
+                    let _ = sender.output(AccountRowOutput::Remove(index.clone()));
+                }
+            },
+            add_row = &adw::EntryRow {
+                set_title: &gettext("Name"),
+                #[watch]
+                show_error: (self.name_error().as_deref(), None),
+                connect_changed[sender] => move |entry| {
+                    sender.input(AccountRowMsg::NameChanged(entry.text().to_string()));
+                }
+            },
+            add_row = &adw::EntryRow {
+                set_title: &gettext("Username"),
+                #[watch]
+                show_error: (self.username_error().as_deref(), None),
+                #[watch]
+                set_text: &self.username,
+                connect_changed[sender] => move |entry| {
+                    sender.input(AccountRowMsg::UsernameChanged(entry.text().to_string()));
+                },
+            },
+            #[name(passwordrow)]
+            add_row = &adw::PasswordEntryRow {
+                set_title: &gettext("Password"),
+                connect_changed[sender, confirmrow] => move |entry| {
+                    set_confirm_style(entry, &confirmrow);
+                    sender.input(AccountRowMsg::PasswordChanged(entry.text().to_string()));
+                }
+            },
+            #[name(confirmrow)]
+            add_row = &adw::PasswordEntryRow {
+                set_title: &gettext("Confirm Password"),
+                connect_changed[sender, passwordrow] => move |entry| {
+                    set_confirm_style(&passwordrow, entry);
+                    sender.input(AccountRowMsg::ConfirmPasswordChanged(entry.text().to_string()));
+                }
+            },
+            add_row = &adw::ActionRow {
+                set_title: &gettext("Use this password anyway"),
+                #[watch]
+                set_visible: self.password_warning().is_some() && !self.policy.enforce,
+                set_activatable: true,
+                connect_activated[overridecheck] => move |_| {
+                    overridecheck.activate();
+                },
+                #[name(overridecheck)]
+                add_prefix = &gtk::CheckButton {
+                    set_valign: gtk::Align::Center,
+                    connect_toggled[sender] => move |check| {
+                        sender.input(AccountRowMsg::SetOverridePolicy(check.is_active()));
+                    }
+                }
+            },
+            add_row = &adw::ActionRow {
+                set_title: &gettext("Administrator"),
+                set_activatable: true,
+                connect_activated[adminswitch] => move |_| {
+                    adminswitch.activate();
+                },
+                #[name(adminswitch)]
+                add_suffix = &gtk::Switch {
+                    set_valign: gtk::Align::Center,
+                    connect_state_set[sender] => move |_, state| {
+                        sender.input(AccountRowMsg::SetAdmin(state));
+                        glib::Propagation::Proceed
+                    }
+                }
+            },
+            add_row = &adw::EntryRow {
+                set_title: &gettext("Extra Groups"),
+                #[watch]
+                show_error: (
+                    check_groups(&self.groups).as_deref(),
+                    Some(gettext("Separate groups with commas or spaces").as_str()),
+                ),
+                connect_text_notify => correct_groups,
+                connect_changed[sender] => move |entry| {
+                    sender.input(AccountRowMsg::GroupsChanged(parse_groups(&entry.text())));
+                }
+            },
+            add_row = &adw::ComboRow {
+                set_title: &gettext("Login Shell"),
+                set_model: Some(&shell_model()),
+                connect_selected_notify[sender] => move |row| {
+                    sender.input(AccountRowMsg::SetShell(selected_shell(row)));
+                }
+            }
+        }
+    }
+
+    fn init_model(policy: Self::Init, _index: &DynamicIndex, _sender: FactorySender<Self>) -> Self {
+        AccountRow {
+            policy,
+            ..Default::default()
+        }
+    }
+
+    fn update(&mut self, message: Self::Input, sender: FactorySender<Self>) {
+        match message {
+            AccountRowMsg::NameChanged(name) => {
+                // Keep suggesting a username until one is typed in
+                if self.username == suggest_username(&self.name) {
+                    self.username = suggest_username(&name);
+                }
+                self.name = name;
+            }
+            AccountRowMsg::UsernameChanged(username) => self.username = username,
+            AccountRowMsg::PasswordChanged(password) => self.password = password,
+            AccountRowMsg::ConfirmPasswordChanged(confirm_password) => {
+                self.confirm_password = confirm_password
+            }
+            AccountRowMsg::SetAdmin(admin) => self.admin = admin,
+            AccountRowMsg::GroupsChanged(groups) => self.groups = groups,
+            AccountRowMsg::SetShell(shell) => self.shell = shell,
+            AccountRowMsg::SetOverridePolicy(override_policy) => {
+                self.override_policy = override_policy
+            }
+            // Sent by the user page itself while checking, so it is not told again
+            AccountRowMsg::SetDuplicate(duplicate) => {
+                self.duplicate = duplicate;
+                return;
+            }
+        }
+        let _ = sender.output(AccountRowOutput::Changed);
+    }
+}
@@ -1,4 +1,7 @@
-use crate::{ui::window::AppMsg, utils::language::get_languages};
+use crate::{
+    ui::window::AppMsg,
+    utils::language::{formats_preview, get_languages, supported_locale, FormatsPreview},
+};
 use adw::prelude::*;
 use gettextrs::gettext;
 use log::{info, trace};
@@ -10,15 +13,31 @@ pub struct WelcomeModel {
     selected: Option<String>,
     selectiongroup: gtk::CheckButton,
     expanders: Vec<adw::ExpanderRow>,
+    /// Locale for regional formats, `None` to use the language
+    formats: Option<String>,
+    /// Locales in the order of the formats row, starting with the language
+    formatlocales: Vec<Option<String>>,
+    preview: Option<FormatsPreview>,
 }
 
 #[derive(Debug)]
 pub enum WelcomeMsg {
     ToggleShowall,
     SetSelected(Option<String>),
+    SetFormats(Option<String>),
     CheckSelected,
 }
 
+impl WelcomeModel {
+    fn update_preview(&mut self) {
+        self.preview = self
+            .formats
+            .as_ref()
+            .or(self.selected.as_ref())
+            .and_then(|locale| formats_preview(locale));
+    }
+}
+
 #[relm4::component(pub)]
 impl SimpleComponent for WelcomeModel {
     type Init = ();
@@ -78,8 +97,46 @@ impl SimpleComponent for WelcomeModel {
                             sender.input(WelcomeMsg::ToggleShowall);
                             sender.input(WelcomeMsg::SetSelected(None));
                         }
+                    },
+                    gtk::ListBox {
+                        add_css_class: "boxed-list",
+                        set_selection_mode: gtk::SelectionMode::None,
+                        adw::ComboRow {
+                            #[watch]
+                            set_title: &gettext("Formats"),
+                            #[watch]
+                            set_subtitle: &gettext("Dates, numbers and currencies"),
+                            set_model: Some(&formatsmodel),
+                            connect_selected_notify[sender, locales = model.formatlocales.clone()] => move |row| {
+                                let formats = locales.get(row.selected() as usize).cloned().flatten();
+                                sender.input(WelcomeMsg::SetFormats(formats));
+                            }
+                        },
+                        adw::ActionRow {
+                            #[watch]
+                            set_title: &gettext("Date and Time"),
+                            #[watch]
+                            set_visible: model.preview.is_some(),
+                            #[watch]
+                            set_subtitle: &model.preview.as_ref().map(|p| p.date.to_string()).unwrap_or_default(),
+                        },
+                        adw::ActionRow {
+                            #[watch]
+                            set_title: &gettext("Numbers"),
+                            #[watch]
+                            set_visible: model.preview.is_some(),
+                            #[watch]
+                            set_subtitle: &model.preview.as_ref().map(|p| p.number.to_string()).unwrap_or_default(),
+                        },
+                        adw::ActionRow {
+                            #[watch]
+                            set_title: &gettext("Currency"),
+                            #[watch]
+                            set_visible: model.preview.is_some(),
+                            #[watch]
+                            set_subtitle: &model.preview.as_ref().map(|p| p.currency.to_string()).unwrap_or_default(),
+                        }
                     }
-
                 }
             }
         }
@@ -95,6 +152,9 @@ impl SimpleComponent for WelcomeModel {
             selected: None,
             selectiongroup: gtk::CheckButton::new(),
             expanders: vec![],
+            formats: None,
+            formatlocales: vec![None],
+            preview: None,
             tracker: 0,
         };
 
@@ -116,6 +176,20 @@ impl SimpleComponent for WelcomeModel {
 
         let mut languages = get_languages().into_iter().collect::<Vec<_>>();
         languages.sort_by(|a, b| a.0.cmp(&b.0));
+
+        // Regional formats can be taken from any locale using UTF-8
+        let formatsmodel = gtk::StringList::new(&[&gettext("Same as language")]);
+        let mut formatlocales = languages
+            .iter()
+            .flat_map(|(_, locales)| locales.iter())
+            .filter(|(locale, _)| supported_locale(locale).ends_with("/UTF-8"))
+            .map(|(locale, title)| (locale.to_string(), title.to_string()))
+            .collect::<Vec<_>>();
+        formatlocales.sort_by(|a, b| a.1.cmp(&b.1));
+        for (locale, title) in formatlocales {
+            formatsmodel.append(&title);
+            model.formatlocales.push(Some(locale));
+        }
         for (title, languages) in languages {
             for locale in &shortlangs {
                 if let Some(title) = languages.get(&locale.to_string()) {
@@ -295,6 +369,13 @@ impl SimpleComponent for WelcomeModel {
                         .next()
                         .unwrap_or_default(),
                 );
+                self.update_preview();
+            }
+            WelcomeMsg::SetFormats(formats) => {
+                info!("Selected formats: {:?}", formats);
+                self.formats = formats;
+                self.update_preview();
+                let _ = sender.output(AppMsg::SetFormatsConfig(self.formats.clone()));
             }
             WelcomeMsg::CheckSelected => {
                 trace!("WelcomeMsg::CheckSelected {}", self.selected.is_some());
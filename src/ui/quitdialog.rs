@@ -12,6 +12,8 @@ pub struct QuitDialogModel {
 pub enum QuitDialogMsg {
     Show,
     Cancel,
+    /// Stops the installation and goes back to the summary
+    Stop,
     Quit,
 }
 
@@ -34,10 +36,13 @@ impl SimpleComponent for QuitDialogModel {
             #[watch]
             set_body: &gettext("Quitting while the installation is in progress may leave your system in an unbootable state!"),
             set_default_width: 500,
-            add_response: ("cancel", &gettext("Cancel")),
+            add_response: ("cancel", &gettext("Continue")),
+            add_response: ("stop", &gettext("Cancel Installation")),
             add_response: ("quit", &gettext("Quit")),
             #[watch]
-            set_response_label: ("cancel", &gettext("Cancel")),
+            set_response_label: ("cancel", &gettext("Continue")),
+            #[watch]
+            set_response_label: ("stop", &gettext("Cancel Installation")),
             #[watch]
             set_response_label: ("quit", &gettext("Quit")),
             set_response_appearance: ("quit", adw::ResponseAppearance::Destructive),
@@ -61,6 +66,7 @@ impl SimpleComponent for QuitDialogModel {
         widgets.dialog.connect_response(None, move |_, resp| {
             sender.input(match resp {
                 "cancel" => QuitDialogMsg::Cancel,
+                "stop" => QuitDialogMsg::Stop,
                 "quit" => QuitDialogMsg::Quit,
                 _ => unreachable!(),
             })
@@ -69,7 +75,7 @@ impl SimpleComponent for QuitDialogModel {
         ComponentParts { model, widgets }
     }
 
-    fn update(&mut self, msg: Self::Input, _sender: ComponentSender<Self>) {
+    fn update(&mut self, msg: Self::Input, sender: ComponentSender<Self>) {
         self.reset();
         match msg {
             QuitDialogMsg::Show => {
@@ -78,8 +84,14 @@ impl SimpleComponent for QuitDialogModel {
             QuitDialogMsg::Cancel => {
                 self.set_hidden(true);
             }
+            QuitDialogMsg::Stop => {
+                self.set_hidden(true);
+                let _ = sender.output(AppMsg::CancelInstall);
+            }
+            // Quitting cleans up like stopping, so nothing is left running
             QuitDialogMsg::Quit => {
-                relm4::main_application().quit();
+                self.set_hidden(true);
+                let _ = sender.output(AppMsg::QuitInstall);
             }
         }
     }
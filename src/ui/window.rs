@@ -24,15 +24,17 @@ use crate::{
     },
     utils::{
         i18n::i18n_f,
-        install::{InstallAsyncModel, InstallAsyncMsg},
+        install::{unmount, InstallAnswers, InstallAsyncModel, InstallAsyncMsg},
         language::{get_country, get_lang},
         parse::{parse_config, Choice, ChoiceEnum, IcicleConfig, InstallationConfig, StepType},
+        state::{CommandOutcome, CommandResult},
     },
 };
 use adw::prelude::*;
 use gettextrs::gettext;
 use log::{debug, error, info, trace, warn};
 use relm4::*;
+use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, convert::identity, process::Command};
 
 #[tracker::track]
@@ -73,24 +75,162 @@ pub struct AppModel {
     current_page: u32,
 
     languageconfig: Option<String>,
-    keyboardconfig: Option<String>,
+    formatsconfig: Option<String>,
+    keyboardconfig: Option<KeyboardConfig>,
     timezoneconfig: Option<String>,
     #[tracker::no_eq]
     partitionconfig: Option<PartitionSchema>,
     userconfig: Option<UserConfig>,
+    /// Post-install commands that failed or timed out, but did not stop the installation
+    #[tracker::no_eq]
+    skippedcommands: Vec<CommandResult>,
+    /// Whether to quit once the cancelled installation is cleaned up
+    quitting: bool,
 
     #[tracker::no_eq]
     installworker: WorkerController<InstallAsyncModel>,
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone)]
 pub struct UserConfig {
-    pub name: String,
-    pub username: String,
-    pub password: String,
+    /// Accounts to create, the first one is the main user
+    pub users: Vec<UserAccount>,
+    #[serde(default)]
     pub hostname: String,
+    #[serde(default)]
     pub rootpassword: Option<String>,
+    /// Whether the main user is logged in automatically
+    #[serde(default)]
     pub autologin: bool,
+    /// Whether to enable the SSH server, without password logins
+    #[serde(default)]
+    pub openssh: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone)]
+pub struct UserAccount {
+    pub name: String,
+    pub username: String,
+    pub password: String,
+    /// Whether the user is in the `wheel` group
+    #[serde(default)]
+    pub admin: bool,
+    /// Groups in addition to the default ones
+    #[serde(default)]
+    pub groups: Vec<String>,
+    #[serde(default)]
+    pub shell: Shell,
+    /// Public SSH keys allowed to log in as the user
+    #[serde(default)]
+    pub authorized_keys: Vec<String>,
+    /// Image file shown for the user on the login screen
+    #[serde(default)]
+    pub avatar: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Shell {
+    #[default]
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl Shell {
+    pub const ALL: [Shell; 3] = [Shell::Bash, Shell::Zsh, Shell::Fish];
+
+    /// Name of the shell's package and `programs` option
+    pub fn name(&self) -> &'static str {
+        match self {
+            Shell::Bash => "bash",
+            Shell::Zsh => "zsh",
+            Shell::Fish => "fish",
+        }
+    }
+}
+
+impl UserConfig {
+    pub fn main_user(&self) -> Option<&UserAccount> {
+        self.users.first()
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, PartialEq, Eq, Clone)]
+pub struct KeyboardConfig {
+    /// Layouts as `layout` or `layout+variant`, the first one is the default
+    pub layouts: Vec<String>,
+    /// Keys that switch to the next layout, unused with a single layout
+    #[serde(default)]
+    pub switch: LayoutSwitch,
+}
+
+impl KeyboardConfig {
+    /// Comma separated layouts and variants, as xkb takes them
+    pub fn xkb_layouts(&self) -> (String, String) {
+        let (layouts, variants): (Vec<_>, Vec<_>) = self
+            .layouts
+            .iter()
+            .map(|layout| layout.split_once('+').unwrap_or((layout, "")))
+            .unzip();
+        (layouts.join(","), variants.join(","))
+    }
+
+    /// xkb option switching layouts, `None` with a single layout
+    pub fn xkb_options(&self) -> Option<&'static str> {
+        if self.layouts.len() > 1 {
+            Some(self.switch.option())
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum LayoutSwitch {
+    #[default]
+    #[serde(rename = "grp:win_space_toggle")]
+    SuperSpace,
+    #[serde(rename = "grp:alt_shift_toggle")]
+    AltShift,
+    #[serde(rename = "grp:ctrl_shift_toggle")]
+    CtrlShift,
+    #[serde(rename = "grp:alt_space_toggle")]
+    AltSpace,
+    #[serde(rename = "grp:caps_toggle")]
+    CapsLock,
+}
+
+impl LayoutSwitch {
+    pub const ALL: [LayoutSwitch; 5] = [
+        LayoutSwitch::SuperSpace,
+        LayoutSwitch::AltShift,
+        LayoutSwitch::CtrlShift,
+        LayoutSwitch::AltSpace,
+        LayoutSwitch::CapsLock,
+    ];
+
+    /// Name of the option in xkb's `grp` group
+    pub fn option(&self) -> &'static str {
+        match self {
+            LayoutSwitch::SuperSpace => "grp:win_space_toggle",
+            LayoutSwitch::AltShift => "grp:alt_shift_toggle",
+            LayoutSwitch::CtrlShift => "grp:ctrl_shift_toggle",
+            LayoutSwitch::AltSpace => "grp:alt_space_toggle",
+            LayoutSwitch::CapsLock => "grp:caps_toggle",
+        }
+    }
+
+    /// Keys to press, as shown to the user
+    pub fn keys(&self) -> String {
+        match self {
+            LayoutSwitch::SuperSpace => gettext("Super+Space"),
+            LayoutSwitch::AltShift => gettext("Alt+Shift"),
+            LayoutSwitch::CtrlShift => gettext("Ctrl+Shift"),
+            LayoutSwitch::AltSpace => gettext("Alt+Space"),
+            LayoutSwitch::CapsLock => gettext("Caps Lock"),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -101,7 +241,8 @@ pub enum AppMsg {
     SetStackPage(StackPage),
     SetStackPageConfig(StackPage, Option<InstallationConfig>),
     SetLanguageConfig(Option<String>),
-    SetKeyboardConfig(Option<String>),
+    SetFormatsConfig(Option<String>),
+    SetKeyboardConfig(Option<KeyboardConfig>),
     SetTimezoneConfig(Option<String>),
     SetPartitionConfig(Option<PartitionSchema>),
     SetUserConfig(Option<UserConfig>),
@@ -109,10 +250,18 @@ pub enum AppMsg {
     SetListConfig(String, HashMap<String, Choice>),
 
     Install,
-    FinishInstall,
-    RunNextCommand,
+    CommandSpawned(i32),
+    StepFinished(i32),
+    Retry,
+    /// Asks whether to cancel the running installation
+    ConfirmCancel,
+    CancelInstall,
+    /// Cancels the running installation, then quits
+    QuitInstall,
+    /// The installation was stopped and the target system released
+    Cancelled,
 
-    Finished,
+    Finished(Vec<CommandResult>),
     Error,
 }
 
@@ -150,6 +299,12 @@ impl Component for AppModel {
                     let _ = quitdialog.send(QuitDialogMsg::Show);
                     glib::Propagation::Stop
                 } else {            
+                    if model.page == StackPage::Error {
+                        // Partitions were left mounted for retrying the failed step
+                        if let Err(e) = unmount() {
+                            error!("Failed to unmount partitions: {}", e);
+                        }
+                    }
                     relm4::main_application().quit();
                     glib::Propagation::Proceed
                 }
@@ -313,6 +468,25 @@ impl Component for AppModel {
                                         set_icon_name: Some("emblem-ok-symbolic"),
                                         set_pixel_size: 256,
                                     },
+                                    gtk::Box {
+                                        set_orientation: gtk::Orientation::Vertical,
+                                        set_spacing: 10,
+                                        #[watch]
+                                        set_visible: !model.skippedcommands.is_empty(),
+                                        gtk::Label {
+                                            add_css_class: "heading",
+                                            #[watch]
+                                            set_label: &gettext("Some post install commands did not complete"),
+                                            set_wrap: true,
+                                        },
+                                        gtk::Label {
+                                            add_css_class: "dim-label",
+                                            #[watch]
+                                            set_label: &skipped_label(&model.skippedcommands),
+                                            set_wrap: true,
+                                            set_justify: gtk::Justification::Center,
+                                        },
+                                    },
                                     gtk::Button {
                                         add_css_class: "suggested-action",
                                         add_css_class: "pill",
@@ -353,46 +527,48 @@ impl Component for AppModel {
         let welcomepage = WelcomeModel::builder()
             .launch(())
             .forward(sender.input_sender(), identity);
-        println!("Welcome page launched");
+        debug!("Welcome page launched");
         let keyboardpage = KeyboardModel::builder()
             .launch(())
             .forward(sender.input_sender(), identity);
-        println!("Keyboard page launched");
+        debug!("Keyboard page launched");
         let timezonepage = TimeZoneModel::builder()
             .launch(())
             .forward(sender.input_sender(), identity);
-        println!("Timezone page launched");
+        debug!("Timezone page launched");
         let partitionpage = PartitionModel::builder()
             .launch_with_broker((), &PARTITION_BROKER)
             .forward(sender.input_sender(), identity);
-        println!("Partition page launched");
+        debug!("Partition page launched");
         let userpage = UserModel::builder()
             .launch(())
             .forward(sender.input_sender(), identity);
-        println!("User page launched");
+        debug!("User page launched");
         let summarypage = SummaryModel::builder()
             .launch(())
             .forward(sender.input_sender(), identity);
-        println!("Summary page launched");
+        debug!("Summary page launched");
         let installpage = InstallModel::builder()
             .launch_with_broker(config.branding.to_string(), &INSTALL_BROKER)
             .forward(sender.input_sender(), identity);
-        println!("Install page launched");
+        debug!("Install page launched");
         let installworker = InstallAsyncModel::builder()
             .detach_worker(())
             .forward(sender.input_sender(), identity);
-        println!("Install worker launched");
+        debug!("Install worker launched");
         let errorpage = ErrorModel::builder()
             .launch(())
             .forward(sender.input_sender(), identity);
-        println!("Error page launched");
+        debug!("Error page launched");
         let quitdialog = QuitDialogModel::builder()
             .launch(root.clone().upcast())
             .forward(sender.input_sender(), identity);
-        println!("Quit dialog launched");
+        debug!("Quit dialog launched");
 
-        let res = reqwest::blocking::get(&config.internet_check_url);
-        let startpage = if let Ok(res) = res {
+        let startpage = if !config.needs_internet() {
+            debug!("Every configuration can be installed offline");
+            StackPage::FrontPage
+        } else if let Ok(res) = reqwest::blocking::get(&config.internet_check_url) {
             if res.status().is_success() {
                 StackPage::FrontPage
             } else {
@@ -442,10 +618,13 @@ impl Component for AppModel {
             carouselpages: HashMap::new(),
             current_page: 0,
             languageconfig: None,
+            formatsconfig: None,
             keyboardconfig: None,
             timezoneconfig: None,
             partitionconfig: None,
             userconfig: None,
+            skippedcommands: vec![],
+            quitting: false,
             installworker,
             tracker: 0,
         };
@@ -670,6 +849,7 @@ impl Component for AppModel {
                                         false
                                     },
                                     self.config.default_hostname.to_string(),
+                                    cfg.password_policy.clone(),
                                 ));
                                 self.summary
                                     .emit(SummaryMsg::ShowHostname(hostname.unwrap_or(false)));
@@ -737,6 +917,9 @@ impl Component for AppModel {
                     }
                 }
             }
+            AppMsg::SetFormatsConfig(formats) => {
+                self.formatsconfig = formats;
+            }
             AppMsg::SetKeyboardConfig(keyboard) => {
                 self.keyboardconfig = keyboard;
             }
@@ -758,34 +941,61 @@ impl Component for AppModel {
                 debug!("Installing!");
                 if let Some(config) = &self.installconfig {
                     self.installworker.emit(InstallAsyncMsg::Install(
-                        config.config_id.to_string(),
-                        self.languageconfig.clone(),
-                        self.timezoneconfig.clone(),
-                        self.keyboardconfig.clone(),
-                        Box::new(self.partitionconfig.clone()),
-                        Box::new(self.userconfig.clone()),
-                        self.listconfig.clone(),
-                        config.config_type.clone(),
-                        config.imperative_timezone.clone(),
+                        Box::new(config.clone()),
+                        Box::new(InstallAnswers {
+                            language: self.languageconfig.clone(),
+                            formats: self.formatsconfig.clone(),
+                            timezone: self.timezoneconfig.clone(),
+                            keyboard: self.keyboardconfig.clone(),
+                            partitions: self.partitionconfig.clone(),
+                            user: self.userconfig.clone(),
+                            list: self.listconfig.clone(),
+                        }),
                     ));
                 }
             }
-            AppMsg::FinishInstall => {
-                debug!("Finishing install!");
-                if let Some(config) = &self.installconfig {
-                    self.installworker.emit(InstallAsyncMsg::FinishInstall(
-                        self.timezoneconfig.clone(),
-                        config.imperative_timezone.clone(),
-                        config.commands.clone(),
-                    ));
+            AppMsg::CommandSpawned(pid) => {
+                self.installworker
+                    .emit(InstallAsyncMsg::CommandSpawned(pid));
+            }
+            AppMsg::StepFinished(status) => {
+                debug!("Install step finished with status {}", status);
+                self.installworker
+                    .emit(InstallAsyncMsg::StepFinished(status));
+            }
+            AppMsg::ConfirmCancel => {
+                self.quitdialog.emit(QuitDialogMsg::Show);
+            }
+            AppMsg::CancelInstall => {
+                debug!("Cancelling!");
+                INSTALL_BROKER.send(InstallMsg::Cancel);
+                self.installworker.emit(InstallAsyncMsg::Cancel);
+            }
+            AppMsg::QuitInstall => {
+                debug!("Quitting!");
+                self.quitting = true;
+                INSTALL_BROKER.send(InstallMsg::Cancel);
+                self.installworker.emit(InstallAsyncMsg::Cancel);
+            }
+            AppMsg::Cancelled => {
+                debug!("Cancelled!");
+                if self.quitting {
+                    relm4::main_application().quit();
                 }
+                // The carousel still shows the summary, with every answer kept
+                self.page = StackPage::Carousel;
             }
-            AppMsg::RunNextCommand => {
-                debug!("Running next postinstall command");
-                self.installworker.emit(InstallAsyncMsg::RunNextCommand);
+            AppMsg::Retry => {
+                debug!("Retrying!");
+                self.page = StackPage::Install;
+                self.installworker.emit(InstallAsyncMsg::Retry);
             }
-            AppMsg::Finished => {
+            AppMsg::Finished(results) => {
                 debug!("Finished!");
+                self.skippedcommands = results
+                    .into_iter()
+                    .filter(|result| result.outcome != CommandOutcome::Succeeded)
+                    .collect();
                 self.page = StackPage::Finished;
             }
             AppMsg::Error => {
@@ -809,3 +1019,21 @@ impl Component for AppModel {
         }
     }
 }
+
+/// One line per post-install command that failed or timed out
+fn skipped_label(results: &[CommandResult]) -> String {
+    results
+        .iter()
+        .map(|result| match result.outcome {
+            CommandOutcome::TimedOut => {
+                // Translators: Do NOT translate the '{}'
+                i18n_f("{} (timed out)", &[&gettext(&result.label)])
+            }
+            _ => {
+                // Translators: Do NOT translate the '{}'
+                i18n_f("{} (failed)", &[&gettext(&result.label)])
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
@@ -0,0 +1,240 @@
+use super::{
+    eventlog::{self, Event},
+    install::{
+        cancel, command_decision, command_error, postinstall_commands, run_step, step_counts,
+        CommandDecision, InstallAnswers, StepAction,
+    },
+    parse::InstallationConfig,
+    state::{CommandOutcome, InstallState, InstallStep},
+};
+use anyhow::{anyhow, Result};
+use log::{error, info};
+use std::time::{Duration, Instant};
+
+/// Walks through the steps of an installation, keeping the install state
+/// and the event log up to date. The installer window and unattended mode
+/// only differ in how they run the commands of a step.
+pub struct InstallDriver {
+    config: InstallationConfig,
+    answers: InstallAnswers,
+    state: InstallState,
+    /// When the current step started
+    started: Instant,
+    /// Command run by the current step
+    command: Option<Vec<String>>,
+    /// How often the current post-install command was run
+    attempts: u32,
+}
+
+/// What to do after a step was run, or its command finished
+#[derive(Debug)]
+pub enum DriverAction {
+    /// The step is done, run the next one
+    Next,
+    /// Run the command to completion and pass its exit code to
+    /// [`InstallDriver::command_finished`]
+    Run(Vec<String>),
+    /// Run the same step again after waiting
+    Wait(Duration),
+    /// The step failed, it can be run again with [`InstallDriver::retry`]
+    Failed(anyhow::Error),
+    /// Every step is done
+    Finished,
+}
+
+impl InstallDriver {
+    /// Starts a new installation from its first step
+    pub fn new(config: InstallationConfig, answers: InstallAnswers) -> Result<Self> {
+        let state = InstallState::new(&config.config_id, &step_counts(&config, &answers));
+        state.save()?;
+        eventlog::start(&config.config_id);
+        Ok(Self::with_state(config, answers, state))
+    }
+
+    /// Continues a previous installation from the step that failed
+    pub fn resume(
+        config: InstallationConfig,
+        answers: InstallAnswers,
+        state: InstallState,
+    ) -> Result<Self> {
+        if state.config_id != config.config_id {
+            return Err(anyhow!(
+                "Cannot resume an installation of '{}' with answers for '{}'",
+                state.config_id,
+                config.config_id
+            ));
+        }
+        let mut driver = Self::with_state(config, answers, state);
+        driver.retry()?;
+        Ok(driver)
+    }
+
+    fn with_state(
+        config: InstallationConfig,
+        answers: InstallAnswers,
+        state: InstallState,
+    ) -> Self {
+        InstallDriver {
+            config,
+            answers,
+            state,
+            started: Instant::now(),
+            command: None,
+            attempts: 0,
+        }
+    }
+
+    pub fn config(&self) -> &InstallationConfig {
+        &self.config
+    }
+
+    pub fn answers(&self) -> &InstallAnswers {
+        &self.answers
+    }
+
+    pub fn state(&self) -> &InstallState {
+        &self.state
+    }
+
+    /// Runs the current step. Errors are only returned if the install state
+    /// could not be saved, failed steps are reported as [`DriverAction::Failed`].
+    pub fn run(&mut self) -> Result<DriverAction> {
+        let step = self.state.step;
+        if step == InstallStep::Done {
+            eventlog::log(Event::InstallFinished);
+            return Ok(DriverAction::Finished);
+        }
+        info!("{}", step.description());
+        eventlog::log(Event::StepStarted {
+            step,
+            description: step.description(),
+        });
+        self.started = Instant::now();
+        self.command = None;
+        match run_step(step, &self.config, &self.answers) {
+            Ok(StepAction::Done) => self.complete(),
+            Ok(StepAction::Run(cmd)) => {
+                eventlog::log(Event::CommandStarted {
+                    step,
+                    command: cmd.clone(),
+                });
+                self.command = Some(cmd.clone());
+                Ok(DriverAction::Run(cmd))
+            }
+            Err(e) => {
+                error!("{} failed: {:?}", step.description(), e);
+                self.fail(e, None)
+            }
+        }
+    }
+
+    /// Handles the exit code of the command of the current step. Returns
+    /// `None` if no command was running, e.g. after the installation was
+    /// cancelled.
+    pub fn command_finished(&mut self, status: i32) -> Result<Option<DriverAction>> {
+        let Some(command) = self.command.take() else {
+            return Ok(None);
+        };
+        let step = self.state.step;
+        eventlog::log(Event::CommandFinished {
+            step,
+            command,
+            exit_code: status,
+            duration_ms: eventlog::duration_ms(self.started.elapsed()),
+        });
+        let action = match step {
+            InstallStep::Command(i) => {
+                let commands = postinstall_commands(&self.config, &self.answers);
+                let command = commands
+                    .get(i)
+                    .ok_or_else(|| anyhow!("No post install command {}", i))?;
+                self.attempts += 1;
+                match command_decision(command, status, self.attempts) {
+                    CommandDecision::Next(result) => {
+                        if result.outcome != CommandOutcome::Succeeded {
+                            error!(
+                                "{} exited with status {}, continuing",
+                                command.label(),
+                                status
+                            );
+                        }
+                        self.state.command_results.push(result);
+                        self.complete()?
+                    }
+                    CommandDecision::Retry(delay) => {
+                        info!(
+                            "{} exited with status {}, retrying in {} seconds",
+                            command.label(),
+                            status,
+                            delay.as_secs()
+                        );
+                        DriverAction::Wait(delay)
+                    }
+                    CommandDecision::Abort => {
+                        error!("{} exited with status {}", command.label(), status);
+                        self.fail(anyhow!(command_error(command, status)), Some(status))?
+                    }
+                }
+            }
+            _ if status == 0 => self.complete()?,
+            _ => {
+                error!("{} exited with status {}", step.description(), status);
+                self.fail(
+                    anyhow!("Command exited with status {}", status),
+                    Some(status),
+                )?
+            }
+        };
+        Ok(Some(action))
+    }
+
+    /// Prepares to run the failed step again
+    pub fn retry(&mut self) -> Result<()> {
+        self.attempts = 0;
+        self.state.retry()?;
+        info!(
+            "Resuming installation at: {}",
+            self.state.step.description()
+        );
+        eventlog::log(Event::InstallResumed {
+            config_id: self.state.config_id.to_string(),
+            step: self.state.step,
+        });
+        Ok(())
+    }
+
+    /// Stops the installation, killing the command of the current step
+    pub fn cancel(self, pid: Option<i32>) -> Result<()> {
+        info!(
+            "Cancelling installation at: {}",
+            self.state.step.description()
+        );
+        eventlog::log(Event::InstallCancelled {
+            step: self.state.step,
+        });
+        cancel(pid)
+    }
+
+    fn complete(&mut self) -> Result<DriverAction> {
+        self.attempts = 0;
+        eventlog::log(Event::StepFinished {
+            step: self.state.step,
+            duration_ms: eventlog::duration_ms(self.started.elapsed()),
+        });
+        self.state
+            .advance(&step_counts(&self.config, &self.answers))?;
+        Ok(DriverAction::Next)
+    }
+
+    fn fail(&mut self, error: anyhow::Error, exit_code: Option<i32>) -> Result<DriverAction> {
+        eventlog::log(Event::StepFailed {
+            step: self.state.step,
+            description: self.state.step.description(),
+            error: format!("{:#}", error),
+            exit_code,
+            duration_ms: eventlog::duration_ms(self.started.elapsed()),
+        });
+        self.state.fail()?;
+        Ok(DriverAction::Failed(error))
+    }
+}
@@ -0,0 +1,110 @@
+use super::state::InstallStep;
+use anyhow::{Context, Result};
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    time::Duration,
+};
+
+/// One JSON object per line for every step of an installation
+pub const EVENT_LOG_PATH: &str = "/tmp/icicle-events.jsonl";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    InstallStarted {
+        config_id: String,
+    },
+    InstallResumed {
+        config_id: String,
+        step: InstallStep,
+    },
+    StepStarted {
+        step: InstallStep,
+        description: String,
+    },
+    CommandStarted {
+        step: InstallStep,
+        command: Vec<String>,
+    },
+    CommandFinished {
+        step: InstallStep,
+        command: Vec<String>,
+        exit_code: i32,
+        duration_ms: u64,
+    },
+    StepFinished {
+        step: InstallStep,
+        duration_ms: u64,
+    },
+    StepFailed {
+        step: InstallStep,
+        description: String,
+        error: String,
+        exit_code: Option<i32>,
+        duration_ms: u64,
+    },
+    InstallFinished,
+    InstallCancelled {
+        step: InstallStep,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Record {
+    /// ISO 8601 in local time
+    timestamp: String,
+    #[serde(flatten)]
+    event: Event,
+}
+
+/// Starts a new event log, discarding the one of any previous installation
+pub fn start(config_id: &str) {
+    if let Err(e) = fs::remove_file(EVENT_LOG_PATH) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            error!("Failed to remove {}: {}", EVENT_LOG_PATH, e);
+        }
+    }
+    log(Event::InstallStarted {
+        config_id: config_id.to_string(),
+    });
+}
+
+/// Appends an event. Failing to write the log never stops an installation.
+pub fn log(event: Event) {
+    if let Err(e) = write(event) {
+        error!("Failed to write event log: {:?}", e);
+    }
+}
+
+fn write(event: Event) -> Result<()> {
+    let timestamp = glib::DateTime::now_local()
+        .and_then(|now| now.format_iso8601())
+        .map(|now| now.to_string())
+        .unwrap_or_default();
+    let mut line = serde_json::to_string(&Record { timestamp, event })?;
+    line.push('\n');
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(EVENT_LOG_PATH)
+        .with_context(|| format!("Failed to open {}", EVENT_LOG_PATH))?
+        .write_all(line.as_bytes())?;
+    Ok(())
+}
+
+pub fn duration_ms(duration: Duration) -> u64 {
+    duration.as_millis() as u64
+}
+
+/// The last failed step, with its timestamp
+pub fn last_failure() -> Option<(String, Event)> {
+    let f = fs::read_to_string(EVENT_LOG_PATH).ok()?;
+    f.lines()
+        .rev()
+        .filter_map(|line| serde_json::from_str::<Record>(line).ok())
+        .find(|record| matches!(record.event, Event::StepFailed { .. }))
+        .map(|record| (record.timestamp, record.event))
+}
@@ -1,4 +1,14 @@
-use super::parse::{Choice, ConfigType};
+use super::{
+    driver::{DriverAction, InstallDriver},
+    parse::{
+        Channel, Choice, ConfigType, FailurePolicy, InjectedFile, InstallationConfig,
+        OfflineInstall, PostInstallCommand, StepType,
+    },
+    password,
+    progress::{Phase, INSTALL_LOG_PATH},
+    state::{CommandOutcome, CommandResult, InstallStep, StepCounts},
+    template::{PlaceholderRegistry, TemplateContext},
+};
 use crate::{
     config::{LIBEXECDIR, SYSCONFDIR},
     ui::{
@@ -6,45 +16,61 @@ use crate::{
             install::{InstallMsg, INSTALL_BROKER},
             partitions::PartitionSchema,
         },
-        window::{AppMsg, UserConfig},
+        window::{AppMsg, KeyboardConfig, UserConfig},
     },
 };
 use anyhow::{anyhow, Context, Result};
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use relm4::*;
 use std::{
     collections::HashMap,
     fs,
     io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
     process::{Command, Stdio},
+    time::Duration,
 };
 
 pub struct InstallAsyncModel {
-    username: Option<String>,
-    password: Option<String>,
-    rootpassword: Option<String>,
-    postinstall_commands: Vec<String>,
+    driver: Option<InstallDriver>,
+    /// Installation that could not be started, started again on retry
+    unstarted: Option<(Box<InstallationConfig>, Box<InstallAnswers>)>,
+    /// Process of the command run in the terminal
+    pid: Option<i32>,
+}
+
+/// Everything the user chose, either in the installer pages or in an answer file
+#[derive(Debug, Clone, Default)]
+pub struct InstallAnswers {
+    pub language: Option<String>,
+    /// Locale for dates, numbers and the like, if different from `language`
+    pub formats: Option<String>,
+    pub timezone: Option<String>,
+    pub keyboard: Option<KeyboardConfig>,
+    pub partitions: Option<PartitionSchema>,
+    pub user: Option<UserConfig>,
+    pub list: HashMap<String, HashMap<String, Choice>>,
+}
+
+impl InstallAnswers {
+    pub fn hostname(&self) -> Option<String> {
+        self.user.as_ref().map(|u| u.hostname.clone())
+    }
 }
 
 #[derive(Debug)]
 pub enum InstallAsyncMsg {
-    Install(
-        String,
-        Option<String>,
-        Option<String>,
-        Option<String>,
-        Box<Option<PartitionSchema>>,
-        Box<Option<UserConfig>>,
-        HashMap<String, HashMap<String, Choice>>, // Listconfig
-        ConfigType,
-        bool,
-    ),
-    FinishInstall(
-        Option<String>, //timezone,
-        bool,           // Imperative timezone
-        Vec<String>,    // Commands
-    ),
-    RunNextCommand,
+    Install(Box<InstallationConfig>, Box<InstallAnswers>),
+    /// Runs the current step of the installation
+    RunStep,
+    /// The command run in the terminal by the current step exited
+    StepFinished(i32),
+    /// The command of the current step was started in the terminal
+    CommandSpawned(i32),
+    /// Runs the failed step again
+    Retry,
+    /// Stops the installation and releases the target system
+    Cancel,
 }
 
 impl Worker for InstallAsyncModel {
@@ -54,258 +80,607 @@ impl Worker for InstallAsyncModel {
 
     fn init(_parent_window: Self::Init, _sender: ComponentSender<Self>) -> Self {
         InstallAsyncModel {
-            username: None,
-            password: None,
-            rootpassword: None,
-            postinstall_commands: vec![],
+            driver: None,
+            unstarted: None,
+            pid: None,
         }
     }
 
     fn update(&mut self, msg: Self::Input, sender: ComponentSender<Self>) {
         match msg {
-            InstallAsyncMsg::Install(
-                id,
-                language,
-                timezone,
-                keyboard,
-                partitions,
-                user,
-                listconfig,
-                configtype,
-                imperative_timezone,
-            ) => {
-                self.username = user.as_ref().as_ref().map(|u| u.username.clone());
-                self.password = user.as_ref().as_ref().map(|u| u.password.clone());
-                self.rootpassword = user.as_ref().as_ref().and_then(|u| u.rootpassword.clone());
-                let hostname = user
-                    .as_ref()
-                    .as_ref()
-                    .map(|u| u.hostname.clone())
-                    .unwrap_or_else(|| "nixos".to_string());
-                let archout = match Command::new("uname")
-                    .arg("-m")
-                    .output()
-                    .context("Failed to get architecture")
-                {
-                    Ok(o) => o,
+            InstallAsyncMsg::Install(config, answers) => {
+                match InstallDriver::new(config.as_ref().clone(), answers.as_ref().clone()) {
+                    Ok(driver) => {
+                        self.driver = Some(driver);
+                        self.unstarted = None;
+                        sender.input(InstallAsyncMsg::RunStep);
+                    }
                     Err(e) => {
-                        error!("Failed to get architecture: {}", e);
+                        error!("{:?}", e);
+                        self.unstarted = Some((config, answers));
                         let _ = sender.output(AppMsg::Error);
-                        return;
                     }
-                };
-                let arch = String::from_utf8_lossy(&archout.stdout).trim().to_string();
-
-                // Step 0: Clear /tmp/icicle
-                info!("Step 0: Clear /tmp/icicle");
-                fn clear() -> Result<()> {
-                    Command::new("pkexec")
-                        .arg("umount")
-                        .arg("-R")
-                        .arg("/tmp/icicle")
-                        .output()?;
-                    Command::new("pkexec")
-                        .arg("rm")
-                        .arg("-rf")
-                        .arg("/tmp/icicle")
-                        .output()?;
-                    Ok(())
                 }
-                if let Err(e) = clear() {
-                    error!("Failed to clear /tmp/icicle: {}", e);
-                    let _ = sender.output(AppMsg::Error);
+            }
+            InstallAsyncMsg::RunStep => {
+                let Some(driver) = &mut self.driver else {
                     return;
+                };
+                let state = driver.state();
+                if state.step != InstallStep::Done {
+                    INSTALL_BROKER.send(InstallMsg::SetPhase(step_phase(
+                        state.step,
+                        driver.config(),
+                        driver.answers(),
+                    )));
                 }
-
-                // Step 1: Setup and mount partitions
-                info!("Step 1: Setup and mount partitions");
-                if let Err(e) = partition(*partitions.clone()) {
-                    error!("Failed to partition: {}", e);
-                    let _ = sender.output(AppMsg::Error);
+                let action = driver.run();
+                self.handle(&sender, action);
+            }
+            InstallAsyncMsg::StepFinished(status) => {
+                self.pid = None;
+                // Commands of a cancelled installation exit once they are stopped
+                let Some(driver) = &mut self.driver else {
                     return;
+                };
+                match driver.command_finished(status) {
+                    Ok(Some(action)) => self.handle(&sender, Ok(action)),
+                    Ok(None) => {}
+                    Err(e) => self.handle(&sender, Err(e)),
                 }
-
-                // Step 2: Generate base config
-                info!("Step 2: Generate base config");
-                if let Err(e) = Command::new("pkexec")
-                    .arg("nixos-generate-config")
-                    .arg("--root")
-                    .arg("/tmp/icicle")
-                    .output()
-                {
-                    error!("Failed to generate base config: {}", e);
-                    let _ = sender.output(AppMsg::Error);
+            }
+            InstallAsyncMsg::CommandSpawned(pid) => {
+                debug!("Step command started with pid {}", pid);
+                self.pid = Some(pid);
+            }
+            InstallAsyncMsg::Cancel => {
+                let Some(driver) = self.driver.take() else {
+                    let _ = sender.output(AppMsg::Cancelled);
                     return;
+                };
+                if let Err(e) = driver.cancel(self.pid.take()) {
+                    error!("Failed to clean up the cancelled installation: {:?}", e);
                 }
-
-                if configtype == ConfigType::Snowfall {
-                    // Move /tmp/icicle/etc/nixos/hardware-configuration.nix to /tmp/icicle/etc/nixos/systems/{ARCH}-linux/{HOSTNAME}/hardware.nix
-                    Command::new("pkexec")
-                        .arg("mkdir")
-                        .arg("-p")
-                        .arg(format!(
-                            "/tmp/icicle/etc/nixos/systems/{}-linux/{}",
-                            arch, hostname
-                        ))
-                        .output()
-                        .unwrap();
-                    Command::new("pkexec")
-                        .arg("mv")
-                        .arg("/tmp/icicle/etc/nixos/hardware-configuration.nix")
-                        .arg(format!(
-                            "/tmp/icicle/etc/nixos/systems/{}-linux/{}/hardware.nix",
-                            arch, hostname
-                        ))
-                        .output()
-                        .unwrap();
-                    // Remove /tmp/icicle/etc/nixos/configuration.nix
-                    Command::new("pkexec")
-                        .arg("rm")
-                        .arg("/tmp/icicle/etc/nixos/configuration.nix")
-                        .output()
-                        .unwrap();
-                }
-
-                // Step 3: Make configuration base on language, timezone, keyboard, and user
-                info!("Step 3: Make configuration");
-
-                let mut mbrdisk = None;
-                if let Some(partitions) = partitions.as_ref() {
-                    match partitions {
-                        PartitionSchema::FullDisk(disk) => {
-                            mbrdisk = Some(disk.to_string());
-                        }
-                        PartitionSchema::Custom(partitions) => {
-                            for part in partitions.values() {
-                                if part.mountpoint == Some("/".to_string()) {
-                                    mbrdisk = Some(part.device.to_string());
-                                }
-                            }
-                        }
+                let _ = sender.output(AppMsg::Cancelled);
+            }
+            InstallAsyncMsg::Retry => {
+                if let Some(driver) = &mut self.driver {
+                    if let Err(e) = driver.retry() {
+                        error!("{:?}", e);
                     }
-                }
-
-                if let Err(e) = makeconfig(MakeConfig {
-                    id,
-                    language,
-                    timezone,
-                    keyboard,
-                    user: *user.clone(),
-                    list: listconfig,
-                    bootdisk: mbrdisk,
-                    imperative_timezone,
-                }) {
-                    error!("Failed to make config: {}", e);
+                    sender.input(InstallAsyncMsg::RunStep);
+                } else if let Some((config, answers)) = self.unstarted.take() {
+                    // Nothing ran yet, so the installation starts over
+                    sender.input(InstallAsyncMsg::Install(config, answers));
+                } else {
                     let _ = sender.output(AppMsg::Error);
-                    return;
                 }
+            }
+        }
+    }
+}
 
-                // Step 4: Install NixOS
-                info!("Step 4: Install NixOS");
-                if let Some(hostname) = user.as_ref().as_ref().map(|u| u.hostname.clone()) {
-                    INSTALL_BROKER.send(InstallMsg::Install(
-                        vec![
-                            "/usr/bin/env",
-                            "pkexec",
-                            "nixos-install",
-                            "--root",
-                            "/tmp/icicle",
-                            "--no-root-passwd",
-                            "--no-channel-copy",
-                            "--flake",
-                            &format!("/tmp/icicle/etc/nixos#{}", hostname),
-                        ]
-                        .into_iter()
-                        .map(|s| s.to_string())
-                        .collect(),
-                    ));
+impl InstallAsyncModel {
+    /// Carries out what the driver asks for after a step or its command
+    fn handle(&self, sender: &ComponentSender<Self>, action: Result<DriverAction>) {
+        let Some(driver) = &self.driver else {
+            return;
+        };
+        match action {
+            Ok(DriverAction::Next) => sender.input(InstallAsyncMsg::RunStep),
+            Ok(DriverAction::Run(cmd)) => {
+                if driver.state().step == InstallStep::InstallNixos {
+                    INSTALL_BROKER.send(InstallMsg::Install(cmd));
                 } else {
-                    error!("No hostname found");
-                    let _ = sender.output(AppMsg::Error);
+                    INSTALL_BROKER.send(InstallMsg::PostInstall(cmd));
                 }
             }
-            InstallAsyncMsg::FinishInstall(timezone, imperative_timezone, mut commands) => {
-                // Step 5: Set user passwords
-                info!("Step 5: Set user passwords");
-                fn setuserpasswd(username: Option<String>, password: Option<String>) -> Result<()> {
-                    let mut passwdcmd = Command::new("pkexec")
-                        .arg("nixos-enter")
-                        .arg("--root")
-                        .arg("/tmp/icicle")
-                        .arg("-c")
-                        .arg("chpasswd -c SHA512")
-                        .stdin(Stdio::piped())
-                        .spawn()?;
-                    let passwdstdin = passwdcmd
-                        .stdin
-                        .as_mut()
-                        .context("Failed to get password stdin")?;
-                    passwdstdin.write_all(
-                        format!(
-                            "{}:{}",
-                            username.context("No username found")?,
-                            password.context("No password found")?
-                        )
-                        .as_bytes(),
-                    )?;
-                    match passwdcmd.wait() {
-                        Err(e) => {
-                            error!("Failed to set password: {}", e);
-                        }
-                        Ok(status) => {
-                            if !status.success() {
-                                error!("Failed to set password");
-                            }
-                        }
-                    }
-                    Ok(())
+            Ok(DriverAction::Wait(delay)) => {
+                let input = sender.input_sender().clone();
+                std::thread::spawn(move || {
+                    std::thread::sleep(delay);
+                    input.emit(InstallAsyncMsg::RunStep);
+                });
+            }
+            Ok(DriverAction::Failed(_)) => {
+                let _ = sender.output(AppMsg::Error);
+            }
+            Ok(DriverAction::Finished) => {
+                let _ = sender.output(AppMsg::Finished(driver.state().command_results.clone()));
+            }
+            Err(e) => {
+                error!("{:?}", e);
+                let _ = sender.output(AppMsg::Error);
+            }
+        }
+    }
+}
+
+/// Progress phase shown while a step runs
+fn step_phase(step: InstallStep, config: &InstallationConfig, answers: &InstallAnswers) -> Phase {
+    match step {
+        InstallStep::PreInstallHook(_) | InstallStep::Clear | InstallStep::Partition => {
+            Phase::Partitioning
+        }
+        InstallStep::GenerateConfig
+        | InstallStep::MakeConfig
+        | InstallStep::PostPartitionHook(_)
+        | InstallStep::SetChannels => Phase::Configuring,
+        InstallStep::InstallNixos => Phase::Evaluating,
+        InstallStep::InstallFiles | InstallStep::SetPasswords | InstallStep::Done => {
+            Phase::Finishing
+        }
+        InstallStep::Command(i) => {
+            let commands = postinstall_commands(config, answers);
+            Phase::PostInstall {
+                current: i,
+                total: commands.len(),
+                label: commands.get(i).and_then(|command| command.label.clone()),
+            }
+        }
+    }
+}
+
+pub fn step_counts(config: &InstallationConfig, answers: &InstallAnswers) -> StepCounts {
+    StepCounts {
+        pre_install_hooks: config.pre_install_hooks.len(),
+        post_partition_hooks: config.post_partition_hooks.len(),
+        commands: postinstall_commands(config, answers).len(),
+    }
+}
+
+/// What running an installation step produced
+#[derive(Debug)]
+pub enum StepAction {
+    /// The step is done
+    Done,
+    /// The step continues with a command that has to be run to completion
+    Run(Vec<String>),
+}
+
+/// Runs a single step of the installation. Steps can be run again after
+/// they failed, without repeating the steps before them.
+pub fn run_step(
+    step: InstallStep,
+    config: &InstallationConfig,
+    answers: &InstallAnswers,
+) -> Result<StepAction> {
+    match step {
+        InstallStep::PreInstallHook(i) => {
+            let hook = config
+                .pre_install_hooks
+                .get(i)
+                .with_context(|| format!("No pre-install hook {}", i))?;
+            info!("Running pre-install hook: {}", hook);
+            return Ok(StepAction::Run(hook_command(hook)));
+        }
+        InstallStep::Clear => clear().context("Failed to clear /tmp/icicle")?,
+        InstallStep::Partition => {
+            partition(answers.partitions.clone()).context("Failed to partition")?
+        }
+        InstallStep::GenerateConfig => {
+            generateconfig(config, answers).context("Failed to generate base config")?
+        }
+        InstallStep::MakeConfig => {
+            makeconfig(&MakeConfig::new(config, answers, ConfigDestination::Target))
+                .context("Failed to make config")?
+        }
+        InstallStep::PostPartitionHook(i) => {
+            let hook = config
+                .post_partition_hooks
+                .get(i)
+                .with_context(|| format!("No post-partition hook {}", i))?;
+            info!("Running post-partition hook: {}", hook);
+            return Ok(StepAction::Run(hook_command(hook)));
+        }
+        // nixos-install builds from and copies the channels of the live system
+        InstallStep::SetChannels => {
+            if config.config_type == ConfigType::Legacy && config.offline.is_none() {
+                setchannels(&config.channels).context("Failed to set up channels")?
+            }
+        }
+        InstallStep::InstallNixos => {
+            return Ok(StepAction::Run(installcommand(config, answers)?));
+        }
+        InstallStep::InstallFiles => {
+            let makeconfig = MakeConfig::new(config, answers, ConfigDestination::Target);
+            installfiles(&makeconfig, &config.files).context("Failed to install files")?;
+            installavatars(&makeconfig).context("Failed to install user avatars")?;
+        }
+        // Passwords are part of the generated configuration, except for
+        // prebuilt closures that were not built from it
+        InstallStep::SetPasswords => {
+            if let (Some(OfflineInstall::System(_)), Some(user)) = (&config.offline, &answers.user)
+            {
+                for account in &user.users {
+                    setuserpasswd(&account.username, &account.password).with_context(|| {
+                        format!("Failed to set password for {}", account.username)
+                    })?;
                 }
-                if let Err(e) = setuserpasswd(self.username.clone(), self.password.clone()) {
-                    error!("Failed to set user password: {}", e);
-                    let _ = sender.output(AppMsg::Error);
-                    return;
+                if let Some(rootpasswd) = &user.rootpassword {
+                    setuserpasswd("root", rootpasswd).context("Failed to set root password")?;
                 }
+            }
+        }
+        InstallStep::Command(i) => {
+            let commands = postinstall_commands(config, answers);
+            let command = commands
+                .get(i)
+                .with_context(|| format!("No post install command {}", i))?;
+            info!("Running post install command: {}", command.label());
+            return Ok(StepAction::Run(postinstall_command(command)));
+        }
+        InstallStep::Done => {}
+    }
+    Ok(StepAction::Done)
+}
 
-                // Step 6: Set root password
-                info!("Step 6: Set root password if specified");
-                if let Some(rootpasswd) = &self.rootpassword {
-                    if let Err(e) = setuserpasswd(Some("root".to_string()), Some(rootpasswd.clone())) {
-                        error!("Failed to set root password: {}", e);
-                        let _ = sender.output(AppMsg::Error);
-                        return;
-                    }
-                }
+fn generateconfig(config: &InstallationConfig, answers: &InstallAnswers) -> Result<()> {
+    let hostname = answers.hostname().context("No hostname found")?;
+    let arch = get_arch()?;
 
-                if imperative_timezone {
-                    if let Some(timezone) = timezone {
-                        commands.insert(0, format!("ln -sf ../etc/zoneinfo/{} /etc/localtime", timezone));
-                    }
-                }
+    Command::new("pkexec")
+        .arg("nixos-generate-config")
+        .arg("--root")
+        .arg("/tmp/icicle")
+        .output()?;
+
+    let hardware = fill_path(config.hardware_path(), &arch, &hostname);
+    if hardware != "hardware-configuration.nix" {
+        let target = format!("/tmp/icicle/etc/nixos/{}", hardware);
+        debug!("Moving hardware-configuration.nix to {}", target);
+        Command::new("pkexec")
+            .arg("mkdir")
+            .arg("-p")
+            .arg(
+                target
+                    .rsplit_once('/')
+                    .map(|(dir, _)| dir)
+                    .unwrap_or(&target),
+            )
+            .output()?;
+        let status = Command::new("pkexec")
+            .arg("mv")
+            .arg("/tmp/icicle/etc/nixos/hardware-configuration.nix")
+            .arg(&target)
+            .status()?;
+        if !status.success() {
+            return Err(anyhow!(
+                "Failed to move hardware configuration to {}",
+                target
+            ));
+        }
+    }
+    if config.config_type != ConfigType::Legacy {
+        // Flakes bring their own configuration, remove /tmp/icicle/etc/nixos/configuration.nix
+        Command::new("pkexec")
+            .arg("rm")
+            .arg("/tmp/icicle/etc/nixos/configuration.nix")
+            .output()?;
+    }
+    Ok(())
+}
+
+/// Command line running `nixos-install`
+fn installcommand(config: &InstallationConfig, answers: &InstallAnswers) -> Result<Vec<String>> {
+    let hostname = answers.hostname().context("No hostname found")?;
+    let mut installcmd = vec![
+        "nixos-install".to_string(),
+        "--root".to_string(),
+        "/tmp/icicle".to_string(),
+        "--no-root-passwd".to_string(),
+    ];
+    match &config.offline {
+        Some(OfflineInstall::System(system)) => {
+            // The closure is already in the store, the configuration is only
+            // used by later rebuilds
+            installcmd.push("--no-channel-copy".to_string());
+            installcmd.push("--system".to_string());
+            installcmd.push(system.to_string());
+        }
+        _ if config.config_type != ConfigType::Legacy => {
+            installcmd.push("--no-channel-copy".to_string());
+            installcmd.push("--flake".to_string());
+            installcmd.push(format!(
+                "/tmp/icicle/etc/nixos#{}",
+                config.flake_output(&hostname)
+            ));
+        }
+        _ => {}
+    }
+    if !config.substituters.is_empty() {
+        installcmd.extend([
+            "--option".to_string(),
+            "extra-substituters".to_string(),
+            config.substituters.join(" "),
+        ]);
+    }
+    if !config.trusted_public_keys.is_empty() {
+        installcmd.extend([
+            "--option".to_string(),
+            "extra-trusted-public-keys".to_string(),
+            config.trusted_public_keys.join(" "),
+        ]);
+    }
+    if config.offline.is_some() {
+        installcmd.extend([
+            "--option".to_string(),
+            "substitute".to_string(),
+            "false".to_string(),
+        ]);
+    }
+    // Copy the output to a log that is followed for progress. Piping also
+    // makes nix print one line per path instead of its interactive progress bar.
+    Ok(vec![
+        "/usr/bin/env".to_string(),
+        "pkexec".to_string(),
+        "bash".to_string(),
+        "-c".to_string(),
+        format!(
+            "set -o pipefail; {} 2>&1 | tee {}",
+            installcmd
+                .iter()
+                .map(|arg| shell_quote(arg))
+                .collect::<Vec<_>>()
+                .join(" "),
+            INSTALL_LOG_PATH
+        ),
+    ])
+}
+
+fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+/// Commands run inside the installed system once `nixos-install` succeeded
+pub fn postinstall_commands(
+    config: &InstallationConfig,
+    answers: &InstallAnswers,
+) -> Vec<PostInstallCommand> {
+    let mut commands = config.commands.clone();
+    if config.imperative_timezone {
+        if let Some(timezone) = &answers.timezone {
+            commands.insert(
+                0,
+                PostInstallCommand::new(&format!(
+                    "ln -sf ../etc/zoneinfo/{} /etc/localtime",
+                    timezone
+                )),
+            );
+        }
+    }
+    if config.config_type == ConfigType::Legacy {
+        // Register the channels in the installed system so that it can be updated
+        for channel in config.channels.iter().rev() {
+            commands.insert(
+                0,
+                PostInstallCommand::new(&format!(
+                    "nix-channel --add {} {}",
+                    shell_quote(&channel.url),
+                    shell_quote(&channel.name)
+                )),
+            );
+        }
+    }
+    commands
+}
+
+/// Exit status of `timeout` when the command ran out of time
+const TIMEOUT_STATUS: i32 = 124;
+
+/// What to do after a post-install command exited
+#[derive(Debug, PartialEq, Eq)]
+pub enum CommandDecision {
+    /// Record the result and move on to the next step
+    Next(CommandResult),
+    /// Run the command again after the delay
+    Retry(Duration),
+    /// Stop the installation
+    Abort,
+}
+
+/// Applies the failure policy of a command that exited with `status` after
+/// being run `attempts` times
+pub fn command_decision(
+    command: &PostInstallCommand,
+    status: i32,
+    attempts: u32,
+) -> CommandDecision {
+    let result = |outcome| CommandResult {
+        label: command.label().to_string(),
+        outcome,
+        attempts,
+    };
+    if status == 0 {
+        return CommandDecision::Next(result(CommandOutcome::Succeeded));
+    }
+    match command.on_failure {
+        FailurePolicy::Abort => CommandDecision::Abort,
+        FailurePolicy::Continue if status == TIMEOUT_STATUS && command.timeout.is_some() => {
+            CommandDecision::Next(result(CommandOutcome::TimedOut))
+        }
+        FailurePolicy::Continue => CommandDecision::Next(result(CommandOutcome::Failed)),
+        FailurePolicy::Retry { times, delay } if attempts <= times => {
+            CommandDecision::Retry(Duration::from_secs(delay))
+        }
+        FailurePolicy::Retry { .. } => CommandDecision::Abort,
+    }
+}
+
+/// Why a post-install command failed
+pub fn command_error(command: &PostInstallCommand, status: i32) -> String {
+    match command.timeout {
+        Some(timeout) if status == TIMEOUT_STATUS => {
+            format!("Command timed out after {} seconds", timeout)
+        }
+        _ => format!("Command exited with status {}", status),
+    }
+}
+
+/// Command line running a hook as root in the live system. The mounted
+/// target system is passed as `ICICLE_ROOT`.
+pub fn hook_command(hook: &str) -> Vec<String> {
+    vec![
+        "/usr/bin/env".to_string(),
+        "pkexec".to_string(),
+        "env".to_string(),
+        "ICICLE_ROOT=/tmp/icicle".to_string(),
+        "bash".to_string(),
+        "-c".to_string(),
+        hook.to_string(),
+    ]
+}
+
+/// Command line running a post-install command inside the installed system
+pub fn postinstall_command(command: &PostInstallCommand) -> Vec<String> {
+    let mut cmd = vec!["/usr/bin/env".to_string(), "pkexec".to_string()];
+    if let Some(timeout) = command.timeout {
+        cmd.extend([
+            "timeout".to_string(),
+            "--kill-after=10".to_string(),
+            timeout.to_string(),
+        ]);
+    }
+    cmd.extend([
+        "nixos-enter".to_string(),
+        "--root".to_string(),
+        "/tmp/icicle".to_string(),
+        "-c".to_string(),
+        command.command.to_string(),
+    ]);
+    cmd
+}
+
+fn get_arch() -> Result<String> {
+    let archout = Command::new("uname")
+        .arg("-m")
+        .output()
+        .context("Failed to get architecture")?;
+    Ok(String::from_utf8_lossy(&archout.stdout).trim().to_string())
+}
+
+/// NixOS release of the live system, e.g. `23.11`
+fn get_stateversion() -> Result<String> {
+    let versionout = Command::new("nixos-version")
+        .output()
+        .context("Failed to get nixos version")?;
+    Ok(String::from_utf8_lossy(&versionout.stdout)
+        .get(0..5)
+        .context("Failed to get nixos version")?
+        .to_string())
+}
+
+fn setchannels(channels: &[Channel]) -> Result<()> {
+    if channels.is_empty() {
+        return Ok(());
+    }
+    for channel in channels {
+        let status = Command::new("pkexec")
+            .arg("nix-channel")
+            .arg("--add")
+            .arg(&channel.url)
+            .arg(&channel.name)
+            .status()?;
+        if !status.success() {
+            return Err(anyhow!("Failed to add channel {}", channel.name));
+        }
+    }
+    let status = Command::new("pkexec")
+        .arg("nix-channel")
+        .arg("--update")
+        .status()?;
+    if !status.success() {
+        return Err(anyhow!("Failed to update channels"));
+    }
+    Ok(())
+}
 
-                self.postinstall_commands = commands;
-                sender.input(InstallAsyncMsg::RunNextCommand);
+/// Stops the process tree of `pid`, disables the swap enabled while
+/// partitioning and unmounts /tmp/icicle through icicle-helper
+pub fn cancel(pid: Option<i32>) -> Result<()> {
+    let mut cmd = Command::new("pkexec");
+    cmd.arg(format!("{}/icicle-helper", LIBEXECDIR));
+    cmd.arg("cancel");
+    if let Some(pid) = pid {
+        cmd.arg("--pid").arg(pid.to_string());
+    }
+    let output = cmd.output()?;
+    debug!(
+        "Cancel output: {}",
+        String::from_utf8_lossy(&output.stdout).trim()
+    );
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Failed to cancel: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+/// Unmounts everything below /tmp/icicle through icicle-helper
+pub fn unmount() -> Result<()> {
+    Command::new("pkexec")
+        .arg(&format!("{}/icicle-helper", LIBEXECDIR))
+        .arg("unmount")
+        .output()?;
+    Ok(())
+}
+
+/// Disables swap left on by an earlier attempt, then unmounts and removes
+/// /tmp/icicle through icicle-helper
+fn clear() -> Result<()> {
+    let output = Command::new("pkexec")
+        .arg(format!("{}/icicle-helper", LIBEXECDIR))
+        .arg("clear")
+        .output()?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "{}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+fn bootdisk(partitions: Option<&PartitionSchema>) -> Option<String> {
+    let mut mbrdisk = None;
+    if let Some(partitions) = partitions {
+        match partitions {
+            PartitionSchema::FullDisk(disk) => {
+                mbrdisk = Some(disk.to_string());
             }
-            // Step 7: Run commands
-            InstallAsyncMsg::RunNextCommand => {
-                if self.postinstall_commands.is_empty() {
-                    let _ = sender.output(AppMsg::Finished);
-                    return;
+            PartitionSchema::Custom(partitions) => {
+                for part in partitions.values() {
+                    if part.mountpoint == Some("/".to_string()) {
+                        mbrdisk = Some(part.device.to_string());
+                    }
                 }
-                let mut commands = self.postinstall_commands.clone();
-                let active = commands.remove(0);
-                self.postinstall_commands = commands;
-                INSTALL_BROKER.send(InstallMsg::PostInstall(vec![
-                    "/usr/bin/env".to_string(),
-                    "pkexec".to_string(),
-                    "nixos-enter".to_string(),
-                    "--root".to_string(),
-                    "/tmp/icicle".to_string(),
-                    "-c".to_string(),
-                    active
-                ]));
             }
         }
     }
+    mbrdisk
+}
+
+/// Sets a password in the installed system. Only its hash leaves the installer.
+fn setuserpasswd(username: &str, password: &str) -> Result<()> {
+    let mut passwdcmd = Command::new("pkexec")
+        .arg("nixos-enter")
+        .arg("--root")
+        .arg("/tmp/icicle")
+        .arg("-c")
+        .arg("chpasswd -e")
+        .stdin(Stdio::piped())
+        .spawn()?;
+    passwdcmd
+        .stdin
+        .take()
+        .context("Failed to get password stdin")?
+        .write_all(format!("{}:{}\n", username, password::hash(password)?).as_bytes())?;
+    if !passwdcmd.wait()?.success() {
+        return Err(anyhow!("Failed to set password of {}", username));
+    }
+    Ok(())
 }
 
 fn partition(partitions: Option<PartitionSchema>) -> Result<()> {
@@ -349,43 +724,130 @@ fn partition(partitions: Option<PartitionSchema>) -> Result<()> {
 
 pub struct MakeConfig {
     pub id: String,
+    pub config_type: ConfigType,
     pub language: Option<String>,
+    pub formats: Option<String>,
     pub timezone: Option<String>,
-    pub keyboard: Option<String>,
+    pub keyboard: Option<KeyboardConfig>,
     pub user: Option<UserConfig>,
     pub list: HashMap<String, HashMap<String, Choice>>,
     pub bootdisk: Option<String>,
     pub imperative_timezone: bool,
+    pub packages: Vec<String>,
+    pub arch_packages: HashMap<String, Vec<String>>,
+    pub substituters: Vec<String>,
+    pub trusted_public_keys: Vec<String>,
+    pub destination: ConfigDestination,
 }
 
-pub fn makeconfig(makeconfig: MakeConfig) -> Result<()> {
-    /* Configuration keys:
-        @NVIDIAOFFLOAD@ - Enable NVIDIA offloading
-        @BOOTLOADRER@ - Bootloader
-        @NETWORK@ - Network configuration
-        @TIMEZONE@ - Timezone
-        @LOCALE@ - Localization
-        @KEYBOARD@ - Keyboard layout
-        @DESKTOP@ - Desktop environment
-        @AUTOLOGIN@ - Autologin config
-        @PACKAGES@ - Packages to install
-        @STATEVERSION@ - NixOS State version
-    */
-
-    /* Value keys:
-        @HOSTNAME@ - Hostname
-        @USERNAME@ - Username
-        @FULLNAME@ - Full name
-    */
-
-    let efi = distinst_disks::Bootloader::detect() == distinst_disks::Bootloader::Efi;
-    let archout = Command::new("uname")
-        .arg("-m")
-        .output()
-        .context("Failed to get architecture")?;
-    let arch = String::from_utf8_lossy(&archout.stdout).trim().to_string();
+impl MakeConfig {
+    pub fn new(
+        config: &InstallationConfig,
+        answers: &InstallAnswers,
+        destination: ConfigDestination,
+    ) -> Self {
+        // Every list gets a placeholder, even if nothing was selected in it
+        let mut list = answers.list.clone();
+        for step in &config.steps {
+            if let StepType::List { id, .. } = step {
+                list.entry(id.to_string()).or_default();
+            }
+        }
+        MakeConfig {
+            id: config.config_id.to_string(),
+            config_type: config.config_type.clone(),
+            language: answers.language.clone(),
+            formats: answers.formats.clone(),
+            timezone: answers.timezone.clone(),
+            keyboard: answers.keyboard.clone(),
+            user: answers.user.clone(),
+            list,
+            bootdisk: bootdisk(answers.partitions.as_ref()),
+            imperative_timezone: config.imperative_timezone,
+            packages: config.packages.clone(),
+            arch_packages: config.arch_packages.clone(),
+            substituters: config.substituters.clone(),
+            trusted_public_keys: config.trusted_public_keys.clone(),
+            destination,
+        }
+    }
+}
+
+fn template_context(makeconfig: &MakeConfig) -> Result<TemplateContext> {
+    let arch = get_arch()?;
+    let mut packages = makeconfig.packages.clone();
+    if let Some(archpkgs) = makeconfig.arch_packages.get(&arch) {
+        packages.extend(archpkgs.iter().cloned());
+    }
+    Ok(TemplateContext {
+        arch,
+        config_type: makeconfig.config_type.clone(),
+        efi: distinst_disks::Bootloader::detect() == distinst_disks::Bootloader::Efi,
+        bootdisk: makeconfig.bootdisk.clone(),
+        language: makeconfig.language.clone(),
+        formats: makeconfig.formats.clone(),
+        timezone: makeconfig.timezone.clone(),
+        imperative_timezone: makeconfig.imperative_timezone,
+        keyboard: makeconfig.keyboard.clone(),
+        user: makeconfig.user.clone(),
+        hashedpasswords: makeconfig
+            .user
+            .iter()
+            .flat_map(|u| u.users.iter())
+            .map(|u| Ok((u.username.to_string(), password::hash(&u.password)?)))
+            .collect::<Result<HashMap<_, _>>>()?,
+        roothashedpassword: makeconfig
+            .user
+            .as_ref()
+            .and_then(|u| u.rootpassword.as_ref())
+            .map(|p| password::hash(p))
+            .transpose()?,
+        list: makeconfig.list.clone(),
+        stateversion: get_stateversion()?,
+        packages,
+        substituters: makeconfig.substituters.clone(),
+        trusted_public_keys: makeconfig.trusted_public_keys.clone(),
+    })
+}
 
-    fn iterwrite(makeconfig: &MakeConfig, path: &str, efi: bool, arch: &str) -> Result<()> {
+/// Renders a template, failing if any placeholder is unknown or has no value
+fn render(
+    registry: &PlaceholderRegistry,
+    template: &str,
+    context: &TemplateContext,
+    source: &Path,
+) -> Result<String> {
+    let rendered = registry
+        .render(template, context)
+        .with_context(|| format!("Failed to render {}", source.to_string_lossy()))?;
+    // Nix code may use `@name@` itself, e.g. for substituteAll
+    if !rendered.unknown.is_empty() {
+        warn!(
+            "Leaving unknown placeholders in {}: @{}@",
+            source.to_string_lossy(),
+            rendered.unknown.join("@, @")
+        );
+    }
+    if !rendered.unreplaced.is_empty() {
+        return Err(anyhow!(
+            "Failed to render {}: no value for: @{}@",
+            source.to_string_lossy(),
+            rendered.unreplaced.join("@, @")
+        ));
+    }
+    Ok(rendered.text)
+}
+
+pub fn makeconfig(makeconfig: &MakeConfig) -> Result<()> {
+    let context = template_context(makeconfig)?;
+    let registry = PlaceholderRegistry::builtin(makeconfig.list.keys());
+
+    fn iterwrite(
+        makeconfig: &MakeConfig,
+        path: &str,
+        registry: &PlaceholderRegistry,
+        context: &TemplateContext,
+    ) -> Result<()> {
         // Iterate through files in configs/
         for file in (fs::read_dir(
             &format!("{}/icicle/{}/{}", SYSCONFDIR, makeconfig.id, path).replace("//", "/"),
@@ -396,274 +858,337 @@ pub fn makeconfig(makeconfig: MakeConfig) -> Result<()> {
             if file.metadata()?.is_dir() {
                 // Iterate through files in the dir
                 debug!("Iterating through {}", file.path().to_string_lossy());
-                debug!("Path: {}", path);
-                debug!(
-                    "!= {}/icicle/{}/modules/{{efiboot,biosboot}}",
-                    SYSCONFDIR, makeconfig.id
-                );
-                let _ = iterwrite(
+                iterwrite(
                     makeconfig,
                     &format!(
                         "{}/{}",
                         path.trim_end_matches('/'),
                         file.file_name().to_string_lossy()
                     ),
-                    efi,
-                    arch,
-                );
+                    registry,
+                    context,
+                )?;
             } else if file.file_name().to_string_lossy().ends_with(".nix") {
-                let mut config = fs::read_to_string(file.path())?;
-                config = config.replace("@NVIDIAOFFLOAD@", "");
-
-                config = config.replace("@ARCH@", &format!("{}-linux", arch));
+                let template = fs::read_to_string(file.path())?;
+                let config = render(registry, &template, context, &file.path())?;
 
-                if efi {
-                    config = config.replace("@BOOTLOADER@", "");
-                    config = config.replace(
-                        "@BOOTLOADER_MODULE@",
-                        "snowflakeos-modules.nixosModules.efiboot",
-                    )
-                } else {
-                    config = config.replace(
-                        "@BOOTLOADER@",
-                        &format!(
-                            r#"  boot.loader.grub.device = "{}";"#,
-                            makeconfig
-                                .bootdisk
-                                .as_ref()
-                                .context("Failed to get bootloader disk")?
-                        ),
-                    );
-                    config = config.replace(
-                        "@BOOTLOADER_MODULE@",
-                        "snowflakeos-modules.nixosModules.biosboot",
-                    )
-                }
-
-                config = config.replace(
-                    "@NETWORK@",
-                    &format!(
-                        r#"  # Define your hostname.
-  networking.hostName = "{}";"#,
-                        makeconfig
-                            .user
-                            .as_ref()
-                            .map(|x| x.hostname.as_ref())
-                            .unwrap_or("nixos")
-                    ),
+                let target = target_path(
+                    makeconfig,
+                    path,
+                    &file.file_name().to_string_lossy(),
+                    &context.arch,
                 );
+                makeconfig.destination.write(&target, &config)?;
+            } else if file.metadata()?.is_file() {
+                let target = target_path(
+                    makeconfig,
+                    path,
+                    &file.file_name().to_string_lossy(),
+                    &context.arch,
+                );
+                makeconfig.destination.copy(&file.path(), &target)?;
+            }
+        }
+        Ok(())
+    }
 
-                if makeconfig.imperative_timezone {
-                    config = config.replace("@TIMEZONE@", "");
-                } else {
-                    if let Some(tz) = &makeconfig.timezone {
-                        config = config.replace(
-                            "@TIMEZONE@",
-                            &format!(
-                                r#"  # Set your time zone.
-  time.timeZone = "{}";"#,
-                                tz
-                            ),
-                        );
-                    }
-                }
+    fn target_path(makeconfig: &MakeConfig, path: &str, filename: &str, arch: &str) -> String {
+        let hostname = makeconfig
+            .user
+            .as_ref()
+            .map(|x| x.hostname.as_ref())
+            .unwrap_or("nixos");
+        format!("{}/{}", fill_path(path, arch, hostname), filename)
+            .trim_start_matches('/')
+            .to_string()
+    }
 
-                if let Some(locale) = &makeconfig.language {
-                    config = config.replace(
-                        "@LOCALE@",
-                        &format!(
-                            r#"  # Select internationalisation properties.
-  i18n.defaultLocale = "{}";"#,
-                            locale
-                        ),
-                    );
-                }
+    iterwrite(makeconfig, "", &registry, &context)
+}
 
-                if let Some(keymap) = &makeconfig.keyboard {
-                    if keymap.contains('+') {
-                        let mut split = keymap.split('+');
-                        if let (Some(layout), Some(variant)) = (split.next(), split.next()) {
-                            config = config.replace(
-                                "@KEYBOARD@",
-                                &format!(
-                                    r#"  # Set the keyboard layout.
-  services.xserver = {{
-    layout = "{}";
-    xkbVariant = "{}";
-  }};
-  console.useXkbConfig = true;"#,
-                                    layout, variant
-                                ),
-                            );
-                        }
-                    } else {
-                        config = config.replace(
-                            "@KEYBOARD@",
-                            &format!(
-                                r#"  # Set the keyboard layout.
-  services.xserver.layout = "{}";
-  console.useXkbConfig = true;"#,
-                                keymap
-                            ),
-                        );
-                    }
-                }
+/// Places the `files` of an installation configuration, replacing placeholders
+/// in those that ask for it
+pub fn installfiles(makeconfig: &MakeConfig, files: &[InjectedFile]) -> Result<()> {
+    if files.is_empty() {
+        return Ok(());
+    }
+    let context = template_context(makeconfig)?;
+    let registry = PlaceholderRegistry::builtin(makeconfig.list.keys());
+    for file in files {
+        let source = Path::new(SYSCONFDIR).join("icicle").join(&file.source);
+        debug!("Installing {} to {}", source.to_string_lossy(), file.path);
+        let contents = if file.substitute {
+            let template = fs::read_to_string(&source)
+                .with_context(|| format!("Failed to read {}", source.to_string_lossy()))?;
+            render(&registry, &template, &context, &source)?.into_bytes()
+        } else {
+            fs::read(&source)
+                .with_context(|| format!("Failed to read {}", source.to_string_lossy()))?
+        };
+        makeconfig.destination.install(file, &contents)?;
+    }
+    Ok(())
+}
 
-                if let Some(user) = &makeconfig.user {
-                    config = config.replace("@USERNAME@", &user.username);
-                    config = config.replace("@FULLNAME@", &user.name);
-                    config = config.replace("@HOSTNAME@", &user.hostname);
-
-                    let mut autocfg = String::new();
-                    if user.autologin {
-                        autocfg.push_str(&format!(
-                            r#"  # Enable automatic login for the user.
-  services.xserver.displayManager.autoLogin.enable = true;
-  services.xserver.displayManager.autoLogin.user = "{}";
-"#,
-                            user.username
-                        ));
-                        autocfg.push_str(
-                                    r#"  # Workaround for GNOME autologin: https://github.com/NixOS/nixpkgs/issues/103746#issuecomment-945091229
-  systemd.services."getty@tty1".enable = false;
-  systemd.services."autovt@tty1".enable = false;
-"#,
-                                );
-                    }
-                    config = config.replace("@AUTOLOGIN@", &autocfg);
-                }
+/// Where AccountsService keeps user icons and settings in the installed system
+const ACCOUNTSSERVICE_DIR: &str = "/var/lib/AccountsService";
 
-                // List configuration options
-                let mut extrapkgs = vec![];
-                for (id, choices) in makeconfig.list.iter() {
-                    let mut listcfg = String::new();
-                    for (_key, choice) in choices.iter() {
-                        if let Some(pkgs) = &choice.packages {
-                            for pkg in pkgs {
-                                extrapkgs.push(pkg.to_string());
-                            }
-                        }
-                        if let Some(cfg) = &choice.config {
-                            cfg.lines()
-                                .for_each(|x| listcfg.push_str(&format!("  {}\n", x)));
-                        }
-                    }
-                    config = config.replace(&format!("@{}@", id), &listcfg);
-                }
+/// Installs the avatar of every user as their AccountsService icon, so that
+/// the login screen shows it on first boot
+pub fn installavatars(makeconfig: &MakeConfig) -> Result<()> {
+    let Some(user) = &makeconfig.user else {
+        return Ok(());
+    };
+    for account in &user.users {
+        let Some(avatar) = &account.avatar else {
+            continue;
+        };
+        debug!("Installing avatar {} for {}", avatar, account.username);
+        let contents = fs::read(avatar).with_context(|| format!("Failed to read {}", avatar))?;
+        let icon = format!("{}/icons/{}", ACCOUNTSSERVICE_DIR, account.username);
+        makeconfig.destination.install(
+            &InjectedFile {
+                source: avatar.to_string(),
+                path: icon.to_string(),
+                mode: "0644".to_string(),
+                owner: None,
+                substitute: false,
+            },
+            &contents,
+        )?;
+        makeconfig.destination.install(
+            &InjectedFile {
+                source: avatar.to_string(),
+                path: format!("{}/users/{}", ACCOUNTSSERVICE_DIR, account.username),
+                mode: "0600".to_string(),
+                owner: None,
+                substitute: false,
+            },
+            format!("[User]\nIcon={}\nSystemAccount=false\n", icon).as_bytes(),
+        )?;
+    }
+    Ok(())
+}
 
-                config = config.replace(
-                    "@PACKAGES@",
-                    &if extrapkgs.is_empty() {
-                        r#"  # List packages installed in system profile.
-  environment.systemPackages = with pkgs; [
-    firefox
-  ];"#
-                        .to_string()
-                    } else {
-                        format!(
-                            r#"  # List packages installed in system profile.
-  environment.systemPackages = with pkgs; [
-    firefox
-    {}
-  ];"#,
-                            extrapkgs.join("\n    ")
-                        )
-                    },
-                );
+/// Path relative to /etc/nixos with the ARCH and HOSTNAME directories filled in
+fn fill_path(path: &str, arch: &str, hostname: &str) -> String {
+    path.replace("ARCH", &format!("{}-linux", arch))
+        .replace("HOSTNAME", hostname)
+        .trim_start_matches('/')
+        .to_string()
+}
 
-                config = config.replace(
-                    "@STATEVERSION@",
-                    &format!(
-                        r#"  system.stateVersion = "{}"; # Did you read the comment?"#,
-                        String::from_utf8_lossy(
-                            &Command::new("nixos-version")
-                                .output()
-                                .context("Failed to get nixos version")?
-                                .stdout
-                        )
-                        .to_string()
-                        .get(0..5)
-                        .context("Failed to get nixos version")?
-                    ),
-                );
+/// Where the generated NixOS configuration is written to
+#[derive(Debug, Clone)]
+pub enum ConfigDestination {
+    /// `/tmp/icicle/etc/nixos` in the target system, written through icicle-helper
+    Target,
+    /// A local directory, written without any privileges
+    Directory(PathBuf),
+}
 
-                let mut cmd = Command::new("pkexec")
+impl ConfigDestination {
+    fn write(&self, relpath: &str, contents: &str) -> Result<()> {
+        match self {
+            ConfigDestination::Target => {
+                let status = Command::new("pkexec")
                     .arg(&format!("{}/icicle-helper", LIBEXECDIR))
                     .arg("write-file")
                     .arg("--path")
-                    .arg(if path.is_empty() {
-                        format!(
-                            "/tmp/icicle/etc/nixos/{}",
-                            file.file_name().to_string_lossy()
-                        )
-                    } else {
-                        format!(
-                            "/tmp/icicle/etc/nixos/{}/{}",
-                            path.replace("ARCH", &format!("{}-linux", arch)).replace(
-                                "HOSTNAME",
-                                makeconfig
-                                    .user
-                                    .as_ref()
-                                    .map(|x| x.hostname.as_ref())
-                                    .unwrap_or("nixos")
-                            ),
-                            file.file_name().to_string_lossy()
-                        )
-                    })
+                    .arg(format!("/tmp/icicle/etc/nixos/{}", relpath))
                     .arg("--contents")
-                    .arg(config)
-                    .spawn()?;
-                cmd.wait()?;
-            } else if file.metadata()?.is_file() {
+                    .arg(contents)
+                    .spawn()?
+                    .wait()?;
+                if !status.success() {
+                    return Err(anyhow!("Failed to write {}", relpath));
+                }
+            }
+            ConfigDestination::Directory(dir) => {
+                let path = dir.join(relpath);
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::write(&path, contents)
+                    .with_context(|| format!("Failed to write {}", path.to_string_lossy()))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes a file anywhere in the installed system. Directories get the
+    /// file below their `files` subdirectory, without its mode and owner.
+    fn install(&self, file: &InjectedFile, contents: &[u8]) -> Result<()> {
+        match self {
+            ConfigDestination::Target => {
+                let mut cmd = Command::new("pkexec");
+                cmd.arg(&format!("{}/icicle-helper", LIBEXECDIR))
+                    .arg("install-file")
+                    .arg("--path")
+                    .arg(&file.path)
+                    .arg("--mode")
+                    .arg(&file.mode);
+                if let Some(owner) = &file.owner {
+                    cmd.arg("--owner").arg(owner);
+                }
+                let mut child = cmd.stdin(Stdio::piped()).spawn()?;
+                child
+                    .stdin
+                    .take()
+                    .context("Failed to write to stdin")?
+                    .write_all(contents)?;
+                if !child.wait()?.success() {
+                    return Err(anyhow!("Failed to install {}", file.path));
+                }
+            }
+            ConfigDestination::Directory(dir) => {
+                let path = dir.join("files").join(file.path.trim_start_matches('/'));
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::write(&path, contents)
+                    .with_context(|| format!("Failed to write {}", path.to_string_lossy()))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn copy(&self, source: &Path, relpath: &str) -> Result<()> {
+        match self {
+            ConfigDestination::Target => {
+                let target = format!("/tmp/icicle/etc/nixos/{}", relpath);
                 Command::new("pkexec")
                     .arg("mkdir")
                     .arg("-p")
-                    .arg(if path.is_empty() {
-                        "/tmp/icicle/etc/nixos/".to_string()
-                    } else {
-                        format!(
-                            "/tmp/icicle/etc/nixos/{}/",
-                            path.replace("ARCH", &format!("{}-linux", arch)).replace(
-                                "HOSTNAME",
-                                makeconfig
-                                    .user
-                                    .as_ref()
-                                    .map(|x| x.hostname.as_ref())
-                                    .unwrap_or("nixos")
-                            )
-                        )
-                    })
+                    .arg(
+                        target
+                            .rsplit_once('/')
+                            .map(|(dir, _)| dir)
+                            .unwrap_or(&target),
+                    )
                     .spawn()?
                     .wait()?;
-
-                Command::new("pkexec")
+                let status = Command::new("pkexec")
                     .arg("cp")
-                    .arg(file.path().to_string_lossy().to_string())
-                    .arg(if path.is_empty() {
-                        format!(
-                            "/tmp/icicle/etc/nixos/{}",
-                            file.file_name().to_string_lossy()
-                        )
-                    } else {
-                        format!(
-                            "/tmp/icicle/etc/nixos/{}/{}",
-                            path.replace("ARCH", &format!("{}-linux", arch)).replace(
-                                "HOSTNAME",
-                                makeconfig
-                                    .user
-                                    .as_ref()
-                                    .map(|x| x.hostname.as_ref())
-                                    .unwrap_or("nixos")
-                            ),
-                            file.file_name().to_string_lossy()
-                        )
-                    })
+                    .arg(source.to_string_lossy().to_string())
+                    .arg(&target)
                     .spawn()?
                     .wait()?;
+                if !status.success() {
+                    return Err(anyhow!("Failed to copy {}", relpath));
+                }
+            }
+            ConfigDestination::Directory(dir) => {
+                let path = dir.join(relpath);
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::copy(source, &path)
+                    .with_context(|| format!("Failed to copy {}", path.to_string_lossy()))?;
             }
         }
         Ok(())
     }
+}
 
-    iterwrite(&makeconfig, "", efi, &arch)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(on_failure: FailurePolicy, timeout: Option<u64>) -> PostInstallCommand {
+        PostInstallCommand {
+            label: Some("Installing Calculator".to_string()),
+            on_failure,
+            timeout,
+            ..PostInstallCommand::new("flatpak install -y flathub org.gnome.Calculator")
+        }
+    }
+
+    fn next(outcome: CommandOutcome, attempts: u32) -> CommandDecision {
+        CommandDecision::Next(CommandResult {
+            label: "Installing Calculator".to_string(),
+            outcome,
+            attempts,
+        })
+    }
+
+    #[test]
+    fn success() {
+        for policy in [
+            FailurePolicy::Abort,
+            FailurePolicy::Continue,
+            FailurePolicy::Retry { times: 1, delay: 5 },
+        ] {
+            assert_eq!(
+                command_decision(&command(policy, None), 0, 1),
+                next(CommandOutcome::Succeeded, 1)
+            );
+        }
+    }
+
+    #[test]
+    fn abort() {
+        let command = command(FailurePolicy::Abort, Some(60));
+        assert_eq!(command_decision(&command, 1, 1), CommandDecision::Abort);
+        assert_eq!(
+            command_decision(&command, TIMEOUT_STATUS, 1),
+            CommandDecision::Abort
+        );
+    }
+
+    #[test]
+    fn continue_() {
+        let command = command(FailurePolicy::Continue, Some(60));
+        assert_eq!(
+            command_decision(&command, 1, 1),
+            next(CommandOutcome::Failed, 1)
+        );
+        assert_eq!(
+            command_decision(&command, TIMEOUT_STATUS, 1),
+            next(CommandOutcome::TimedOut, 1)
+        );
+        // Only `timeout` itself exits with 124 on a timeout
+        let untimed = PostInstallCommand {
+            timeout: None,
+            ..command
+        };
+        assert_eq!(
+            command_decision(&untimed, TIMEOUT_STATUS, 1),
+            next(CommandOutcome::Failed, 1)
+        );
+    }
+
+    #[test]
+    fn retry() {
+        let command = command(
+            FailurePolicy::Retry {
+                times: 2,
+                delay: 10,
+            },
+            Some(60),
+        );
+        let retry = CommandDecision::Retry(Duration::from_secs(10));
+        assert_eq!(command_decision(&command, 1, 1), retry);
+        assert_eq!(command_decision(&command, TIMEOUT_STATUS, 2), retry);
+        assert_eq!(command_decision(&command, 1, 3), CommandDecision::Abort);
+        assert_eq!(
+            command_decision(&command, 0, 3),
+            next(CommandOutcome::Succeeded, 3)
+        );
+    }
+
+    #[test]
+    fn error() {
+        let timed = command(FailurePolicy::Abort, Some(60));
+        assert_eq!(
+            command_error(&timed, TIMEOUT_STATUS),
+            "Command timed out after 60 seconds"
+        );
+        assert_eq!(command_error(&timed, 2), "Command exited with status 2");
+        let untimed = command(FailurePolicy::Abort, None);
+        assert_eq!(
+            command_error(&untimed, TIMEOUT_STATUS),
+            "Command exited with status 124"
+        );
+    }
 }
@@ -1,9 +1,13 @@
 use anyhow::{Context, Result};
-use gtk::glib::GString;
-use std::collections::HashMap;
+use gtk::glib::{self, GString};
+use std::{
+    collections::HashMap,
+    ffi::{CStr, CString},
+    ptr,
+};
 
-pub fn get_languages() -> HashMap<String, HashMap<String, String>> {
-    let locale_codes = r#"
+/// Locales glibc can build, with their character set, as in its SUPPORTED file
+const LOCALE_CODES: &str = r#"
 aa_DJ.UTF-8/UTF-8
 aa_DJ/ISO-8859-1
 aa_ER/UTF-8
@@ -492,9 +496,10 @@ zh_TW.UTF-8/UTF-8
 zh_TW/BIG5
 zu_ZA.UTF-8/UTF-8
 zu_ZA/ISO-8859-1
-"#
-    .trim()
-    .split('\n');
+"#;
+
+pub fn get_languages() -> HashMap<String, HashMap<String, String>> {
+    let locale_codes = LOCALE_CODES.trim().split('\n');
 
     let mut languages = HashMap::new();
 
@@ -557,3 +562,99 @@ pub fn get_country(code: String) -> Result<String> {
         .context("Invalid country")?
         .to_string())
 }
+
+/// Entry of a locale for `i18n.supportedLocales`, e.g. `de_DE.UTF-8/UTF-8`
+pub fn supported_locale(locale: &str) -> String {
+    LOCALE_CODES
+        .trim()
+        .split('\n')
+        .find(|code| code.split('/').next() == Some(locale))
+        .map(|code| code.to_string())
+        .unwrap_or_else(|| format!("{}/UTF-8", locale))
+}
+
+/// How dates, numbers and amounts of money are written in a locale
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FormatsPreview {
+    pub date: String,
+    pub number: String,
+    pub currency: String,
+}
+
+/// Formats examples with the regional formats of `locale`. Returns `None` if
+/// the locale is not available in the live system.
+pub fn formats_preview(locale: &str) -> Option<FormatsPreview> {
+    let name = CString::new(locale).ok()?;
+    // Only this thread switches to the locale, setlocale would switch every
+    // thread while they may be using it. The character set comes along so
+    // that glib reads the formats in the right encoding.
+    let formats = unsafe {
+        libc::newlocale(
+            libc::LC_CTYPE_MASK
+                | libc::LC_TIME_MASK
+                | libc::LC_NUMERIC_MASK
+                | libc::LC_MONETARY_MASK,
+            name.as_ptr(),
+            ptr::null_mut(),
+        )
+    };
+    if formats.is_null() {
+        return None;
+    }
+    let previous = unsafe { libc::uselocale(formats) };
+    let preview = unsafe {
+        let conv = &*libc::localeconv();
+        let text = |s: *const libc::c_char| CStr::from_ptr(s).to_string_lossy().to_string();
+        let date = glib::DateTime::now_local()
+            .and_then(|now| now.format("%x %X"))
+            .map(|date| date.to_string())
+            .unwrap_or_default();
+        let number = format_amount(
+            1234567,
+            89,
+            &text(conv.thousands_sep),
+            &text(conv.decimal_point),
+        );
+        let mut amount = format_amount(
+            1234,
+            56,
+            &text(conv.mon_thousands_sep),
+            &text(conv.mon_decimal_point),
+        );
+        let symbol = text(conv.currency_symbol);
+        let space = if conv.p_sep_by_space == 1 { " " } else { "" };
+        amount = if conv.p_cs_precedes == 1 {
+            format!("{}{}{}", symbol, space, amount)
+        } else {
+            format!("{}{}{}", amount, space, symbol)
+        };
+        FormatsPreview {
+            date,
+            number,
+            currency: amount,
+        }
+    };
+    unsafe {
+        libc::uselocale(previous);
+        libc::freelocale(formats);
+    }
+    Some(preview)
+}
+
+/// Writes a number with two decimal places, grouping thousands
+fn format_amount(whole: u64, cents: u64, thousands_sep: &str, decimal_point: &str) -> String {
+    let digits = whole.to_string();
+    let mut grouped = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            grouped.push_str(thousands_sep);
+        }
+        grouped.push(digit);
+    }
+    let decimal_point = if decimal_point.is_empty() {
+        "."
+    } else {
+        decimal_point
+    };
+    format!("{}{}{:02}", grouped, decimal_point, cents)
+}
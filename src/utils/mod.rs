@@ -1,4 +1,14 @@
+pub mod driver;
+pub mod eventlog;
 pub mod i18n;
 pub mod install;
 pub mod language;
 pub mod parse;
+pub mod password;
+pub mod progress;
+pub mod ssh;
+pub mod state;
+pub mod template;
+pub mod transliterate;
+pub mod unattended;
+pub mod validate;
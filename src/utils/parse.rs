@@ -11,6 +11,20 @@ pub struct IcicleConfig {
     pub internet_check_url: String,
     pub default_hostname: String,
     pub choices: Vec<ChoiceEnum>,
+    /// Binary caches used by every configuration, besides cache.nixos.org
+    #[serde(default)]
+    pub substituters: Vec<String>,
+    #[serde(default)]
+    pub trusted_public_keys: Vec<String>,
+}
+
+impl IcicleConfig {
+    /// Whether any configuration can only be installed with an internet connection
+    pub fn needs_internet(&self) -> bool {
+        self.choices.iter().any(|choice| {
+            matches!(choice, ChoiceEnum::Configuration { config, .. } if config.offline.is_none())
+        })
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -30,7 +44,7 @@ pub enum ConfigType {
     Snowfall,
     #[default]
     Flakes,
-    Legacy
+    Legacy,
 }
 
 #[derive(Deserialize, Serialize, Default, Clone, Debug)]
@@ -42,8 +56,206 @@ pub struct InstallationConfig {
     #[serde(default)]
     pub imperative_timezone: bool,
     pub steps: Vec<StepType>,
+    /// Run in the live system before partitioning
+    #[serde(default)]
+    pub pre_install_hooks: Vec<String>,
+    /// Run in the live system once the partitions are mounted and the
+    /// configuration is written, before nixos-install
+    #[serde(default)]
+    pub post_partition_hooks: Vec<String>,
+    #[serde(default)]
+    pub commands: Vec<PostInstallCommand>,
+    /// Files placed into the installed system
     #[serde(default)]
-    pub commands: Vec<String>,
+    pub files: Vec<InjectedFile>,
+    /// Packages installed on every architecture
+    #[serde(default = "default_packages")]
+    pub packages: Vec<String>,
+    /// Packages installed only on an architecture as reported by `uname -m`,
+    /// e.g. `x86_64`
+    #[serde(default)]
+    pub arch_packages: HashMap<String, Vec<String>>,
+    /// Channels to set up for `legacy` configurations
+    #[serde(default)]
+    pub channels: Vec<Channel>,
+    /// `nixosConfigurations` attribute to install, defaults to the hostname
+    pub flake_output: Option<String>,
+    /// Where hardware-configuration.nix is moved to, relative to /etc/nixos
+    pub hardware_path: Option<String>,
+    /// Install without an internet connection
+    pub offline: Option<OfflineInstall>,
+    /// Binary caches used besides cache.nixos.org, after the ones of the
+    /// installer configuration
+    #[serde(default)]
+    pub substituters: Vec<String>,
+    #[serde(default)]
+    pub trusted_public_keys: Vec<String>,
+    /// Rules for the passwords of created users
+    #[serde(default)]
+    pub password_policy: PasswordPolicy,
+}
+
+impl InstallationConfig {
+    pub fn flake_output(&self, hostname: &str) -> String {
+        self.flake_output
+            .clone()
+            .unwrap_or_else(|| hostname.to_string())
+    }
+
+    /// Path of the hardware configuration, which may contain the ARCH and
+    /// HOSTNAME directories like the configuration files
+    pub fn hardware_path(&self) -> &str {
+        match &self.hardware_path {
+            Some(path) => path,
+            None => match self.config_type {
+                ConfigType::Snowfall => "systems/ARCH/HOSTNAME/hardware.nix",
+                ConfigType::Flakes | ConfigType::Legacy => "hardware-configuration.nix",
+            },
+        }
+    }
+}
+
+/// How a configuration is installed without an internet connection. The
+/// configuration files are written either way, for later rebuilds.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OfflineInstall {
+    /// Install this system closure from the store of the live system
+    System(String),
+    /// Build the flake without substituters. Its inputs have to be locked and
+    /// already in the store of the live system.
+    Flake,
+}
+
+/// A command run inside the installed system. Either just the command line,
+/// or a map with a `command` and the optional settings.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+#[serde(from = "PostInstallCommandEntry")]
+pub struct PostInstallCommand {
+    pub command: String,
+    /// Shown instead of the command line
+    pub label: Option<String>,
+    pub on_failure: FailurePolicy,
+    /// Seconds after which the command is stopped and counted as failed
+    pub timeout: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PostInstallCommandEntry {
+    Command(String),
+    Detailed {
+        command: String,
+        label: Option<String>,
+        #[serde(default)]
+        on_failure: FailurePolicy,
+        timeout: Option<u64>,
+    },
+}
+
+impl From<PostInstallCommandEntry> for PostInstallCommand {
+    fn from(entry: PostInstallCommandEntry) -> Self {
+        match entry {
+            PostInstallCommandEntry::Command(command) => PostInstallCommand::new(&command),
+            PostInstallCommandEntry::Detailed {
+                command,
+                label,
+                on_failure,
+                timeout,
+            } => PostInstallCommand {
+                command,
+                label,
+                on_failure,
+                timeout,
+            },
+        }
+    }
+}
+
+impl PostInstallCommand {
+    pub fn new(command: &str) -> Self {
+        PostInstallCommand {
+            command: command.to_string(),
+            label: None,
+            on_failure: FailurePolicy::default(),
+            timeout: None,
+        }
+    }
+
+    pub fn label(&self) -> &str {
+        self.label.as_deref().unwrap_or(&self.command)
+    }
+}
+
+/// What happens when a post-install command fails
+#[derive(Deserialize, Serialize, Default, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FailurePolicy {
+    /// Stop the installation
+    #[default]
+    Abort,
+    /// Carry on with the next command
+    Continue,
+    /// Run the command again up to `times` more times, `delay` seconds apart,
+    /// before stopping the installation
+    Retry {
+        times: u32,
+        #[serde(default)]
+        delay: u64,
+    },
+}
+
+/// Rules for user passwords. Passwords breaking them are warned about, and
+/// only rejected if the policy is enforced.
+#[derive(Deserialize, Serialize, Default, Clone, Debug, PartialEq, Eq)]
+pub struct PasswordPolicy {
+    /// Minimum number of characters
+    #[serde(default)]
+    pub min_length: usize,
+    /// Reject common passwords and dictionary words
+    #[serde(default)]
+    pub reject_dictionary: bool,
+    /// Word list used by `reject_dictionary`, one word per line. The word
+    /// list of the live system is used if not set.
+    #[serde(default)]
+    pub dictionary: Option<String>,
+    /// Reject passwords containing the username
+    #[serde(default)]
+    pub reject_username: bool,
+    /// Whether the rules can not be overridden
+    #[serde(default)]
+    pub enforce: bool,
+}
+
+/// A file copied from the live system into the installed system
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct InjectedFile {
+    /// File in the live system, relative to /etc/icicle unless absolute
+    pub source: String,
+    /// Absolute path in the installed system
+    pub path: String,
+    /// Octal permissions
+    #[serde(default = "default_mode")]
+    pub mode: String,
+    /// `user` or `user:group`, by name or id in the installed system
+    pub owner: Option<String>,
+    /// Whether placeholders in the file are replaced like in the configuration
+    #[serde(default)]
+    pub substitute: bool,
+}
+
+fn default_packages() -> Vec<String> {
+    vec!["firefox".to_string()]
+}
+
+fn default_mode() -> String {
+    "0644".to_string()
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct Channel {
+    pub name: String,
+    pub url: String,
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
@@ -79,11 +291,19 @@ pub fn parse_config() -> Result<IcicleConfig> {
     debug!("Parsing config {}/icicle/config.yml", SYSCONFDIR);
     let f = fs::read_to_string(&format!("{}/icicle/config.yml", SYSCONFDIR))?;
     let mut config: IcicleConfig = serde_yaml::from_str(&f)?;
+    let substituters = config.substituters.clone();
+    let trusted_public_keys = config.trusted_public_keys.clone();
     for choice in &mut config.choices {
         match choice {
             ChoiceEnum::Configuration { file, config } => {
                 let f = fs::read_to_string(&format!("{}/icicle/{}", SYSCONFDIR, file))?;
                 *config = serde_yaml::from_str(&f)?;
+                config
+                    .substituters
+                    .splice(0..0, substituters.iter().cloned());
+                config
+                    .trusted_public_keys
+                    .splice(0..0, trusted_public_keys.iter().cloned());
             }
             ChoiceEnum::Live => {}
         }
@@ -0,0 +1,312 @@
+use super::{i18n::ni18n_f, parse::PasswordPolicy};
+use anyhow::{anyhow, Result};
+use gettextrs::gettext;
+use log::{debug, warn};
+use pwhash::sha512_crypt;
+use std::{
+    collections::HashSet,
+    fs,
+    sync::{Arc, Mutex},
+};
+
+/// SHA-512 crypt hash of a password with a random salt, as understood by
+/// `users.users.<name>.initialHashedPassword` and `chpasswd -e`
+pub fn hash(password: &str) -> Result<String> {
+    sha512_crypt::hash(password).map_err(|e| anyhow!("Failed to hash password: {:?}", e))
+}
+
+/// Passwords and words tried first when guessing passwords
+const COMMON_PASSWORDS: &[&str] = &[
+    "123456",
+    "1234567",
+    "12345678",
+    "123456789",
+    "1234567890",
+    "111111",
+    "000000",
+    "123123",
+    "654321",
+    "password",
+    "passw0rd",
+    "qwerty",
+    "qwertz",
+    "azerty",
+    "qwertyuiop",
+    "asdfgh",
+    "asdfghjkl",
+    "zxcvbnm",
+    "abc",
+    "abcd",
+    "abcdef",
+    "letmein",
+    "welcome",
+    "admin",
+    "administrator",
+    "root",
+    "toor",
+    "guest",
+    "user",
+    "test",
+    "default",
+    "changeme",
+    "secret",
+    "login",
+    "hello",
+    "iloveyou",
+    "love",
+    "monkey",
+    "dragon",
+    "master",
+    "shadow",
+    "sunshine",
+    "princess",
+    "football",
+    "baseball",
+    "soccer",
+    "hockey",
+    "superman",
+    "batman",
+    "starwars",
+    "pokemon",
+    "trustno",
+    "freedom",
+    "whatever",
+    "computer",
+    "internet",
+    "summer",
+    "winter",
+    "spring",
+    "autumn",
+    "flower",
+    "cookie",
+    "cheese",
+    "chocolate",
+    "killer",
+    "hunter",
+    "ranger",
+    "buster",
+    "charlie",
+    "michael",
+    "jennifer",
+    "jordan",
+    "linux",
+    "nixos",
+    "snowflake",
+    "ubuntu",
+    "windows",
+];
+
+/// Word lists of the live system, the first one found is used
+const DICTIONARY_PATHS: &[&str] = &[
+    "/usr/share/dict/words",
+    "/usr/share/dict/cracklib-small",
+    "/usr/share/cracklib/cracklib-small",
+];
+
+/// Shorter words are part of too many passwords by chance
+const DICTIONARY_MIN_LENGTH: usize = 4;
+
+/// Last word list read, with its path, so it is not read on every keystroke
+static DICTIONARY: Mutex<Option<(String, Arc<HashSet<String>>)>> = Mutex::new(None);
+
+/// Estimated strength of a password from 0, very weak, to 4, strong
+pub fn strength(password: &str) -> u32 {
+    if password.is_empty() || is_common(password) {
+        return 0;
+    }
+    let bits = entropy(password);
+    if bits < 28.0 {
+        0
+    } else if bits < 36.0 {
+        1
+    } else if bits < 60.0 {
+        2
+    } else if bits < 80.0 {
+        3
+    } else {
+        4
+    }
+}
+
+/// Rough entropy in bits, from the kinds of characters used and the length.
+/// Repeated characters and runs like `abc` or `321` barely count.
+fn entropy(password: &str) -> f64 {
+    let chars = password.chars().collect::<Vec<_>>();
+    let mut pool = 0;
+    if chars.iter().any(|c| c.is_ascii_lowercase()) {
+        pool += 26;
+    }
+    if chars.iter().any(|c| c.is_ascii_uppercase()) {
+        pool += 26;
+    }
+    if chars.iter().any(|c| c.is_ascii_digit()) {
+        pool += 10;
+    }
+    if chars.iter().any(|c| c.is_ascii_punctuation() || *c == ' ') {
+        pool += 33;
+    }
+    if chars.iter().any(|c| !c.is_ascii()) {
+        pool += 100;
+    }
+    let mut length = 0.0;
+    for (i, c) in chars.iter().enumerate() {
+        let repeated = i > 0 && {
+            let (prev, cur) = (chars[i - 1] as i64, *c as i64);
+            (cur - prev).abs() <= 1
+        };
+        length += if repeated { 0.25 } else { 1.0 };
+    }
+    length * (pool as f64).log2()
+}
+
+/// Whether a password is a common one, also with digits or symbols around it
+fn is_common(password: &str) -> bool {
+    is_listed(password, &HashSet::new())
+}
+
+/// Whether a password is a common one or a word of a word list, also with
+/// digits or symbols around it
+fn is_listed(password: &str, words: &HashSet<String>) -> bool {
+    let lower = password.to_lowercase();
+    let word = lower.trim_matches(|c: char| !c.is_alphabetic());
+    COMMON_PASSWORDS.contains(&lower.as_str())
+        || COMMON_PASSWORDS.contains(&word)
+        || (word.chars().count() >= DICTIONARY_MIN_LENGTH && words.contains(word))
+}
+
+/// Words of the configured word list, or of the one of the live system.
+/// Empty if there is none, leaving only the built-in list of common passwords.
+fn dictionary(policy: &PasswordPolicy) -> Arc<HashSet<String>> {
+    let Some(path) = policy.dictionary.clone().or_else(|| {
+        DICTIONARY_PATHS
+            .iter()
+            .find(|path| fs::metadata(path).is_ok())
+            .map(|path| path.to_string())
+    }) else {
+        return Arc::new(HashSet::new());
+    };
+    let mut cached = DICTIONARY.lock().unwrap();
+    if let Some((cachedpath, words)) = cached.as_ref() {
+        if *cachedpath == path {
+            return words.clone();
+        }
+    }
+    let words = match fs::read(&path) {
+        Ok(text) => Arc::new(parse_words(&String::from_utf8_lossy(&text))),
+        Err(e) => {
+            warn!("Failed to read word list {}: {}", path, e);
+            Arc::new(HashSet::new())
+        }
+    };
+    debug!("Read {} words from {}", words.len(), path);
+    *cached = Some((path, words.clone()));
+    words
+}
+
+/// Words of a word list in lowercase. Entries that are not a single word,
+/// like `o'clock`, are skipped.
+fn parse_words(text: &str) -> HashSet<String> {
+    text.lines()
+        .map(|line| line.trim().to_lowercase())
+        .filter(|word| {
+            word.chars().count() >= DICTIONARY_MIN_LENGTH && word.chars().all(char::is_alphabetic)
+        })
+        .collect()
+}
+
+/// Why a password breaks the rules of a policy, empty if it follows them
+pub fn policy_violations(password: &str, username: &str, policy: &PasswordPolicy) -> Vec<String> {
+    let mut violations = vec![];
+    let length = password.chars().count();
+    if length < policy.min_length {
+        violations.push(ni18n_f(
+            "Use at least {} character",
+            "Use at least {} characters",
+            policy.min_length as u32,
+            &[&policy.min_length.to_string()],
+        ));
+    }
+    if policy.reject_dictionary && is_listed(password, &dictionary(policy)) {
+        violations.push(gettext("This is a common password or a dictionary word"));
+    }
+    if policy.reject_username
+        && !username.is_empty()
+        && password.to_lowercase().contains(&username.to_lowercase())
+    {
+        violations.push(gettext("The password must not contain the username"));
+    }
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> PasswordPolicy {
+        PasswordPolicy {
+            min_length: 8,
+            reject_dictionary: true,
+            reject_username: true,
+            // Keeps the tests independent of the word list of the system
+            dictionary: Some("/nonexistent".to_string()),
+            enforce: false,
+        }
+    }
+
+    #[test]
+    fn strength() {
+        assert_eq!(super::strength(""), 0);
+        assert_eq!(super::strength("password"), 0);
+        assert_eq!(super::strength("Password1!"), 0);
+        assert_eq!(super::strength("abcdefgh"), 0);
+        assert_eq!(super::strength("kx7wq2"), 1);
+        assert_eq!(super::strength("Tr0ub4dor&3"), 3);
+        assert_eq!(super::strength("correct horse battery staple"), 4);
+    }
+
+    #[test]
+    fn min_length() {
+        assert_eq!(policy_violations("kx7#Qz", "", &policy()).len(), 1);
+        assert!(policy_violations("kx7#Qz9!", "", &policy()).is_empty());
+        // Counted in characters, not bytes
+        assert_eq!(policy_violations("ÄÖÜäöüß", "", &policy()).len(), 1);
+    }
+
+    #[test]
+    fn username() {
+        assert_eq!(
+            policy_violations("xAlice2023!", "alice", &policy()),
+            vec!["The password must not contain the username".to_string()]
+        );
+        assert!(policy_violations("xAlice2023!", "", &policy()).is_empty());
+        let policy = PasswordPolicy {
+            reject_username: false,
+            ..policy()
+        };
+        assert!(policy_violations("xAlice2023!", "alice", &policy).is_empty());
+    }
+
+    #[test]
+    fn dictionary_words() {
+        assert_eq!(
+            policy_violations("2023dragon!!", "", &policy()),
+            vec!["This is a common password or a dictionary word".to_string()]
+        );
+        let policy = PasswordPolicy {
+            reject_dictionary: false,
+            ..policy()
+        };
+        assert!(policy_violations("2023dragon!!", "", &policy).is_empty());
+
+        let words = parse_words("Sunflower\nit's\ncat\n  meadow  \n");
+        assert_eq!(
+            words,
+            HashSet::from(["sunflower".to_string(), "meadow".to_string()])
+        );
+        assert!(is_listed("1986Sunflower!", &words));
+        assert!(is_listed("#meadow#", &words));
+        assert!(!is_listed("sunflowers", &words));
+        assert!(!is_listed("cat123", &words));
+        assert!(!is_listed("1986Sunflower!", &HashSet::new()));
+    }
+}
@@ -0,0 +1,219 @@
+use regex::Regex;
+use std::time::{Duration, Instant};
+
+/// Output of `nixos-install` is copied here, so that it can be followed
+/// while the command runs in the terminal
+pub const INSTALL_LOG_PATH: &str = "/tmp/icicle-install.log";
+
+/// Part of the installation currently running
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Phase {
+    Partitioning,
+    Configuring,
+    Evaluating,
+    Downloading,
+    Building,
+    Bootloader,
+    /// Files and passwords are set up in the installed system
+    Finishing,
+    /// Post-install command at index `current` of `total` is running, with
+    /// the label of the command if it has one
+    PostInstall {
+        current: usize,
+        total: usize,
+        label: Option<String>,
+    },
+}
+
+impl Phase {
+    /// Share of the whole installation done when this phase starts, and when it ends
+    fn range(&self) -> (f64, f64) {
+        match self {
+            Phase::Partitioning => (0.0, 0.05),
+            Phase::Configuring => (0.05, 0.08),
+            Phase::Evaluating => (0.08, 0.12),
+            Phase::Downloading | Phase::Building => (0.12, 0.9),
+            Phase::Bootloader => (0.9, 0.94),
+            Phase::Finishing => (0.94, 0.95),
+            Phase::PostInstall { .. } => (0.95, 1.0),
+        }
+    }
+}
+
+/// Progress of an installation, fed with the lines `nixos-install` prints
+#[derive(Debug, Clone)]
+pub struct InstallProgress {
+    pub phase: Phase,
+    /// Store paths fetched so far, and announced in total
+    pub fetched: usize,
+    pub fetch_total: usize,
+    /// Derivations built so far, and announced in total
+    pub built: usize,
+    pub build_total: usize,
+    /// When the first path was fetched or built
+    started: Option<Instant>,
+    fetch_re: Regex,
+    build_re: Regex,
+}
+
+impl Default for InstallProgress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InstallProgress {
+    pub fn new() -> Self {
+        InstallProgress {
+            phase: Phase::Partitioning,
+            fetched: 0,
+            fetch_total: 0,
+            built: 0,
+            build_total: 0,
+            started: None,
+            fetch_re: Regex::new(r"^these (\d+) paths will be fetched").unwrap(),
+            build_re: Regex::new(r"^these (\d+) derivations will be built").unwrap(),
+        }
+    }
+
+    /// Moves to a phase that is not reported by `nixos-install` itself
+    pub fn set_phase(&mut self, phase: Phase) {
+        self.phase = phase;
+    }
+
+    pub fn parse_line(&mut self, line: &str) {
+        let line = line.trim();
+        if let Some(n) = self
+            .fetch_re
+            .captures(line)
+            .and_then(|c| c[1].parse::<usize>().ok())
+        {
+            self.fetch_total += n;
+        } else if line.starts_with("this path will be fetched") {
+            self.fetch_total += 1;
+        } else if let Some(n) = self
+            .build_re
+            .captures(line)
+            .and_then(|c| c[1].parse::<usize>().ok())
+        {
+            self.build_total += n;
+        } else if line.starts_with("this derivation will be built") {
+            self.build_total += 1;
+        } else if line.starts_with("copying path ") {
+            self.start();
+            self.fetched += 1;
+            self.phase = Phase::Downloading;
+        } else if line.starts_with("building '") {
+            self.start();
+            self.built += 1;
+            self.phase = Phase::Building;
+        } else if line.starts_with("building the ") {
+            // "building the flake in ..." or "building the configuration in ..."
+            self.phase = Phase::Evaluating;
+        } else if line.starts_with("installing the boot loader") {
+            self.phase = Phase::Bootloader;
+        }
+    }
+
+    fn start(&mut self) {
+        if self.started.is_none() {
+            self.started = Some(Instant::now());
+        }
+    }
+
+    /// Share of the fetching and building done, once anything was announced
+    fn nix_fraction(&self) -> Option<f64> {
+        let total = self.fetch_total + self.build_total;
+        if total == 0 {
+            return None;
+        }
+        Some(((self.fetched + self.built) as f64 / total as f64).min(1.0))
+    }
+
+    /// Share of the whole installation done, between 0 and 1
+    pub fn fraction(&self) -> f64 {
+        let (start, end) = self.phase.range();
+        let done = match self.phase {
+            Phase::Downloading | Phase::Building => self.nix_fraction().unwrap_or(0.0),
+            Phase::PostInstall { current, total, .. } if total > 0 => current as f64 / total as f64,
+            _ => 0.0,
+        };
+        start + (end - start) * done
+    }
+
+    /// Estimated time until fetching and building is done
+    pub fn eta(&self) -> Option<Duration> {
+        if !matches!(self.phase, Phase::Downloading | Phase::Building) {
+            return None;
+        }
+        let done = self.nix_fraction()?;
+        let elapsed = self.started?.elapsed();
+        // Too early for a meaningful estimate
+        if done < 0.02 || elapsed < Duration::from_secs(10) {
+            return None;
+        }
+        Some(elapsed.mul_f64((1.0 - done) / done))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn parses_nixos_install_output() {
+        let mut progress = InstallProgress::new();
+        assert_eq!(progress.phase, Phase::Partitioning);
+        progress.set_phase(Phase::Evaluating);
+        progress.parse_line("building the flake in git+file:///mnt/etc/nixos?dir=.");
+        assert_eq!(progress.phase, Phase::Evaluating);
+        progress.parse_line("these 3 derivations will be built:");
+        progress.parse_line("this derivation will be built:");
+        progress
+            .parse_line("these 5 paths will be fetched (10.50 MiB download, 50.20 MiB unpacked):");
+        progress.parse_line("this path will be fetched (0.01 MiB download, 0.05 MiB unpacked):");
+        assert_eq!((progress.build_total, progress.fetch_total), (4, 6));
+        assert_eq!(progress.phase, Phase::Evaluating);
+
+        progress.parse_line(
+            "copying path '/nix/store/abc-glibc-2.38' from 'https://cache.nixos.org'...",
+        );
+        assert_eq!(progress.phase, Phase::Downloading);
+        assert_eq!(progress.fetched, 1);
+        progress.parse_line("  building '/nix/store/def-etc.drv'...");
+        assert_eq!(progress.phase, Phase::Building);
+        assert_eq!(progress.built, 1);
+        assert_near(progress.fraction(), 0.12 + 0.78 * 2.0 / 10.0);
+
+        progress.parse_line("installing the boot loader...");
+        assert_eq!(progress.phase, Phase::Bootloader);
+        assert_near(progress.fraction(), 0.9);
+        assert_eq!(progress.eta(), None);
+    }
+
+    #[test]
+    fn counts_post_install_commands() {
+        let mut progress = InstallProgress::new();
+        progress.set_phase(Phase::PostInstall {
+            current: 1,
+            total: 4,
+            label: None,
+        });
+        assert_near(progress.fraction(), 0.95 + 0.05 / 4.0);
+        progress.set_phase(Phase::Finishing);
+        assert_near(progress.fraction(), 0.94);
+    }
+
+    #[test]
+    fn fraction_without_announced_paths() {
+        let mut progress = InstallProgress::new();
+        progress.parse_line(
+            "copying path '/nix/store/abc-glibc-2.38' from 'https://cache.nixos.org'...",
+        );
+        assert_near(progress.fraction(), 0.12);
+    }
+}
@@ -0,0 +1,143 @@
+use super::i18n::i18n_f;
+use anyhow::{anyhow, bail, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use gettextrs::gettext;
+
+/// Key types accepted by OpenSSH in `authorized_keys`
+const KEY_TYPES: &[&str] = &[
+    "ssh-ed25519",
+    "ssh-rsa",
+    "ecdsa-sha2-nistp256",
+    "ecdsa-sha2-nistp384",
+    "ecdsa-sha2-nistp521",
+    "sk-ssh-ed25519@openssh.com",
+    "sk-ecdsa-sha2-nistp256@openssh.com",
+];
+
+/// Parses public keys in the `authorized_keys` format, one per line, with or
+/// without options in front of them. Empty lines and comments are skipped.
+pub fn parse_authorized_keys(text: &str) -> Result<Vec<String>> {
+    let mut keys = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        check_key(line).map_err(|e| {
+            anyhow!(i18n_f(
+                // Translators: Do NOT translate the '{}'
+                "Line {}: {}",
+                &[&(i + 1).to_string(), &e.to_string()]
+            ))
+        })?;
+        keys.push(line.to_string());
+    }
+    Ok(keys)
+}
+
+/// Checks that a key has a known type and that its data is a key of that type
+pub fn check_key(key: &str) -> Result<()> {
+    let mut parts = skip_options(key).split_whitespace();
+    let (Some(keytype), Some(data)) = (parts.next(), parts.next()) else {
+        bail!(gettext("Not a public key"));
+    };
+    if !KEY_TYPES.contains(&keytype) {
+        // Translators: Do NOT translate the '{}'
+        bail!(i18n_f("Unknown key type “{}”", &[keytype]));
+    }
+    let blob = STANDARD
+        .decode(data)
+        .map_err(|_| anyhow!(gettext("Key data is not valid base64")))?;
+    // The key data starts with its type as a length prefixed string
+    let embedded = blob
+        .get(..4)
+        .map(|len| u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize)
+        .and_then(|len| blob.get(4..4 + len));
+    if embedded != Some(keytype.as_bytes()) {
+        // Translators: Do NOT translate the '{}'
+        bail!(i18n_f("Key data does not match key type “{}”", &[keytype]));
+    }
+    Ok(())
+}
+
+/// Skips the options in front of a key, e.g. `from="10.0.0.1",no-pty`.
+/// Options are only recognized when a known key type follows them.
+fn skip_options(key: &str) -> &str {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in key.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                let (first, rest) = (&key[..i], key[i..].trim_start());
+                let keytype = rest.split_whitespace().next().unwrap_or_default();
+                if KEY_TYPES.contains(&first) || !KEY_TYPES.contains(&keytype) {
+                    return key;
+                }
+                return rest;
+            }
+            _ => {}
+        }
+    }
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ED25519: &str =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIHbR0kgM0ZF2Z4f9I0AupOfdnSm8oOxhe3qlyBJq1yFG lab@admin";
+    const RSA: &str = "ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAAAAQE=";
+
+    fn error(key: &str) -> String {
+        check_key(key).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn keys() {
+        assert!(check_key(ED25519).is_ok());
+        assert!(check_key(RSA).is_ok());
+        assert_eq!(error("ssh-ed25519"), "Not a public key");
+        assert_eq!(
+            error("ssh-dss AAAAB3NzaC1kc3M="),
+            "Unknown key type “ssh-dss”"
+        );
+        assert_eq!(
+            error("ssh-ed25519 not-base64!"),
+            "Key data is not valid base64"
+        );
+        assert_eq!(
+            error("ssh-ed25519 AAAAB3NzaC1yc2EAAAADAQABAAAAAQE="),
+            "Key data does not match key type “ssh-ed25519”"
+        );
+    }
+
+    #[test]
+    fn options() {
+        assert!(check_key(&format!("no-pty,from=\"10.0.0.0/8\" {}", ED25519)).is_ok());
+        assert!(check_key(&format!("command=\"echo \\\"a b\\\"\" {}", RSA)).is_ok());
+        // Without a known key type after them, options are not skipped
+        assert_eq!(
+            error("no-pty ssh-dss AAAAB3NzaC1kc3M="),
+            "Unknown key type “no-pty”"
+        );
+    }
+
+    #[test]
+    fn authorized_keys() {
+        let text = format!("# laptop\n{}\n\n  {}  \n", ED25519, RSA);
+        assert_eq!(
+            parse_authorized_keys(&text).unwrap(),
+            vec![ED25519.to_string(), RSA.to_string()]
+        );
+        assert_eq!(
+            parse_authorized_keys(&format!("{}\nssh-ed25519\n", ED25519))
+                .unwrap_err()
+                .to_string(),
+            "Line 2: Not a public key"
+        );
+    }
+}
@@ -0,0 +1,181 @@
+use anyhow::{Context, Result};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::{fs, os::unix::fs::OpenOptionsExt, path::Path};
+
+/// Where the progress of the current installation is kept, so that a failed
+/// step can be retried without starting over
+pub const STATE_PATH: &str = "/tmp/icicle-state.json";
+
+/// Steps of an installation, in the order they are run
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum InstallStep {
+    /// Pre-install hook at the given index
+    PreInstallHook(usize),
+    Clear,
+    Partition,
+    GenerateConfig,
+    MakeConfig,
+    /// Post-partition hook at the given index
+    PostPartitionHook(usize),
+    /// Point the channels of the live system at the configured ones
+    SetChannels,
+    InstallNixos,
+    /// Place the files listed in the installation configuration
+    InstallFiles,
+    SetPasswords,
+    /// Post-install command at the given index
+    Command(usize),
+    Done,
+}
+
+/// Number of steps of each kind that run once per entry in the installation configuration
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StepCounts {
+    pub pre_install_hooks: usize,
+    pub post_partition_hooks: usize,
+    pub commands: usize,
+}
+
+impl InstallStep {
+    pub fn description(&self) -> String {
+        match self {
+            InstallStep::PreInstallHook(i) => format!("Run pre-install hook {}", i + 1),
+            InstallStep::Clear => "Clear /tmp/icicle".to_string(),
+            InstallStep::Partition => "Setup and mount partitions".to_string(),
+            InstallStep::GenerateConfig => "Generate base config".to_string(),
+            InstallStep::MakeConfig => "Make configuration".to_string(),
+            InstallStep::PostPartitionHook(i) => format!("Run post-partition hook {}", i + 1),
+            InstallStep::SetChannels => "Set up channels".to_string(),
+            InstallStep::InstallNixos => "Install NixOS".to_string(),
+            InstallStep::InstallFiles => "Install extra files".to_string(),
+            InstallStep::SetPasswords => "Set user passwords".to_string(),
+            InstallStep::Command(i) => format!("Run post install command {}", i + 1),
+            InstallStep::Done => "Done".to_string(),
+        }
+    }
+
+    /// Every step of an installation, in order, ending with `Done`
+    pub fn all(counts: &StepCounts) -> Vec<InstallStep> {
+        let mut steps = vec![];
+        steps.extend((0..counts.pre_install_hooks).map(InstallStep::PreInstallHook));
+        steps.extend([
+            InstallStep::Clear,
+            InstallStep::Partition,
+            InstallStep::GenerateConfig,
+            InstallStep::MakeConfig,
+        ]);
+        steps.extend((0..counts.post_partition_hooks).map(InstallStep::PostPartitionHook));
+        steps.extend([
+            InstallStep::SetChannels,
+            InstallStep::InstallNixos,
+            InstallStep::InstallFiles,
+            InstallStep::SetPasswords,
+        ]);
+        steps.extend((0..counts.commands).map(InstallStep::Command));
+        steps.push(InstallStep::Done);
+        steps
+    }
+
+    /// The step following this one
+    pub fn next(&self, counts: &StepCounts) -> InstallStep {
+        let steps = Self::all(counts);
+        steps
+            .iter()
+            .position(|step| step == self)
+            .and_then(|i| steps.get(i + 1))
+            .copied()
+            .unwrap_or(InstallStep::Done)
+    }
+}
+
+/// Progress of an installation. Only the step is stored, the answers are
+/// kept in memory or re-read from the answer file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct InstallState {
+    pub config_id: String,
+    pub step: InstallStep,
+    /// Whether `step` was attempted and failed
+    #[serde(default)]
+    pub failed: bool,
+    /// Outcome of every post-install command run so far
+    #[serde(default)]
+    pub command_results: Vec<CommandResult>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CommandResult {
+    pub label: String,
+    pub outcome: CommandOutcome,
+    /// How often the command was run
+    pub attempts: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandOutcome {
+    Succeeded,
+    /// Failed, and the installation continued without it
+    Failed,
+    /// Stopped after its timeout, and the installation continued without it
+    TimedOut,
+}
+
+impl InstallState {
+    pub fn new(config_id: &str, counts: &StepCounts) -> Self {
+        InstallState {
+            config_id: config_id.to_string(),
+            step: InstallStep::all(counts)[0],
+            failed: false,
+            command_results: vec![],
+        }
+    }
+
+    pub fn load() -> Result<Option<Self>> {
+        if !Path::new(STATE_PATH).exists() {
+            return Ok(None);
+        }
+        let f = fs::read_to_string(STATE_PATH)
+            .with_context(|| format!("Failed to read {}", STATE_PATH))?;
+        let state =
+            serde_json::from_str(&f).with_context(|| format!("Failed to parse {}", STATE_PATH))?;
+        Ok(Some(state))
+    }
+
+    pub fn save(&self) -> Result<()> {
+        debug!("Saving install state: {:?}", self);
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(STATE_PATH)
+            .with_context(|| format!("Failed to open {}", STATE_PATH))?;
+        serde_json::to_writer_pretty(&mut file, self)
+            .with_context(|| format!("Failed to write {}", STATE_PATH))?;
+        Ok(())
+    }
+
+    /// Marks the current step as done and moves on to the next one
+    pub fn advance(&mut self, counts: &StepCounts) -> Result<()> {
+        self.step = self.step.next(counts);
+        self.failed = false;
+        self.save()
+    }
+
+    pub fn fail(&mut self) -> Result<()> {
+        self.failed = true;
+        self.save()
+    }
+
+    /// Prepares to run the failed step again. A failed partitioning may have
+    /// left partitions mounted, so it is retried from a clean /tmp/icicle.
+    pub fn retry(&mut self) -> Result<()> {
+        if self.step == InstallStep::Partition {
+            self.step = InstallStep::Clear;
+        }
+        self.failed = false;
+        self.save()
+    }
+}
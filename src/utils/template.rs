@@ -0,0 +1,836 @@
+use super::{
+    language::supported_locale,
+    parse::{Choice, ConfigType},
+};
+use crate::ui::window::{KeyboardConfig, Shell, UserAccount, UserConfig};
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::collections::HashMap;
+
+/// Everything the configuration templates are rendered from.
+/// Values depending on the running system (architecture, firmware, NixOS
+/// version) are detected by the caller, so rendering itself is pure.
+#[derive(Debug, Clone, Default)]
+pub struct TemplateContext {
+    /// Architecture as reported by `uname -m`, e.g. `x86_64`
+    pub arch: String,
+    pub config_type: ConfigType,
+    pub efi: bool,
+    /// Disk GRUB is installed to on BIOS systems
+    pub bootdisk: Option<String>,
+    pub language: Option<String>,
+    /// Locale for regional formats, if different from `language`
+    pub formats: Option<String>,
+    pub timezone: Option<String>,
+    pub imperative_timezone: bool,
+    pub keyboard: Option<KeyboardConfig>,
+    pub user: Option<UserConfig>,
+    /// Crypt hashes of the passwords of every user by username, and of root
+    pub hashedpasswords: HashMap<String, String>,
+    pub roothashedpassword: Option<String>,
+    pub list: HashMap<String, HashMap<String, Choice>>,
+    /// NixOS release, e.g. `23.11`
+    pub stateversion: String,
+    /// Base packages for the architecture, before the ones of the list choices
+    pub packages: Vec<String>,
+    /// Binary caches used besides cache.nixos.org
+    pub substituters: Vec<String>,
+    pub trusted_public_keys: Vec<String>,
+}
+
+/// Renders a placeholder, `None` if the context has no value for it
+type RenderFn = Box<dyn Fn(&TemplateContext) -> Result<Option<String>>>;
+
+/// Result of rendering a single template
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rendered {
+    pub text: String,
+    /// `@FOO@` tokens that are not registered
+    pub unknown: Vec<String>,
+    /// Registered placeholders the context has no value for
+    pub unreplaced: Vec<String>,
+}
+
+pub struct PlaceholderRegistry {
+    placeholders: HashMap<String, RenderFn>,
+    token: Regex,
+}
+
+impl Default for PlaceholderRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PlaceholderRegistry {
+    /// An empty registry
+    pub fn new() -> Self {
+        PlaceholderRegistry {
+            placeholders: HashMap::new(),
+            token: Regex::new(r"@([A-Za-z_][A-Za-z0-9_]*)@").unwrap(),
+        }
+    }
+
+    /// Registers a placeholder, replacing any previous one with the same name
+    pub fn register<F>(&mut self, name: &str, render: F)
+    where
+        F: Fn(&TemplateContext) -> Result<Option<String>> + 'static,
+    {
+        self.placeholders.insert(name.to_string(), Box::new(render));
+    }
+
+    /// All built-in placeholders, plus one for each list id in `list_ids`
+    pub fn builtin<'a>(list_ids: impl IntoIterator<Item = &'a String>) -> Self {
+        let mut registry = Self::new();
+        registry.register("NVIDIAOFFLOAD", |_| Ok(Some(String::new())));
+        registry.register("ARCH", |ctx| Ok(Some(format!("{}-linux", ctx.arch))));
+        registry.register("BOOTLOADER", bootloader);
+        registry.register("BOOTLOADER_MODULE", |ctx| {
+            Ok(Some(if ctx.efi {
+                "snowflakeos-modules.nixosModules.efiboot".to_string()
+            } else {
+                "snowflakeos-modules.nixosModules.biosboot".to_string()
+            }))
+        });
+        registry.register("NETWORK", network);
+        registry.register("TIMEZONE", timezone);
+        registry.register("LOCALE", locale);
+        registry.register("KEYBOARD", keyboard);
+        registry.register("USERNAME", |ctx| {
+            Ok(main_user(ctx).map(|u| u.username.to_string()))
+        });
+        registry.register("FULLNAME", |ctx| {
+            Ok(main_user(ctx).map(|u| u.name.to_string()))
+        });
+        registry.register("HOSTNAME", |ctx| {
+            Ok(ctx.user.as_ref().map(|u| u.hostname.to_string()))
+        });
+        registry.register("HASHEDPASSWORD", |ctx| {
+            Ok(main_user(ctx).and_then(|u| ctx.hashedpasswords.get(&u.username).cloned()))
+        });
+        registry.register("USERS", users);
+        registry.register("OPENSSH", openssh);
+        registry.register("ROOTPASSWORD", rootpassword);
+        registry.register("AUTOLOGIN", autologin);
+        registry.register("PACKAGES", packages);
+        registry.register("SUBSTITUTERS", substituters);
+        registry.register("STATEVERSION", |ctx| {
+            Ok(Some(format!(
+                r#"  system.stateVersion = "{}"; # Did you read the comment?"#,
+                ctx.stateversion
+            )))
+        });
+        for id in list_ids {
+            let listid = id.to_string();
+            registry.register(id, move |ctx| Ok(Some(list(ctx, &listid))));
+        }
+        registry
+    }
+
+    /// Replaces every registered `@FOO@` token in `template`. Tokens that are
+    /// unknown, or have no value in `context`, are left as is and reported.
+    pub fn render(&self, template: &str, context: &TemplateContext) -> Result<Rendered> {
+        let mut text = String::with_capacity(template.len());
+        let mut unknown = vec![];
+        let mut unreplaced = vec![];
+        let mut values: HashMap<&str, Option<String>> = HashMap::new();
+        let mut last = 0;
+        for captures in self.token.captures_iter(template) {
+            let token = captures.get(0).unwrap();
+            let name = captures.get(1).unwrap().as_str();
+            text.push_str(&template[last..token.start()]);
+            last = token.end();
+            let Some(render) = self.placeholders.get(name) else {
+                if !unknown.iter().any(|x| x == name) {
+                    unknown.push(name.to_string());
+                }
+                text.push_str(token.as_str());
+                continue;
+            };
+            if !values.contains_key(name) {
+                let value =
+                    render(context).with_context(|| format!("Failed to render @{}@", name))?;
+                values.insert(name, value);
+            }
+            match &values[name] {
+                Some(value) => text.push_str(value),
+                None => {
+                    if !unreplaced.iter().any(|x| x == name) {
+                        unreplaced.push(name.to_string());
+                    }
+                    text.push_str(token.as_str());
+                }
+            }
+        }
+        text.push_str(&template[last..]);
+        Ok(Rendered {
+            text,
+            unknown,
+            unreplaced,
+        })
+    }
+}
+
+fn bootloader(ctx: &TemplateContext) -> Result<Option<String>> {
+    // Flake based configurations get the bootloader from @BOOTLOADER_MODULE@
+    let legacy = ctx.config_type == ConfigType::Legacy;
+    if ctx.efi {
+        Ok(Some(if legacy {
+            r#"  # Use the systemd-boot EFI boot loader.
+  boot.loader.systemd-boot.enable = true;
+  boot.loader.efi.canTouchEfiVariables = true;"#
+                .to_string()
+        } else {
+            String::new()
+        }))
+    } else {
+        let device = format!(
+            r#"  boot.loader.grub.device = "{}";"#,
+            ctx.bootdisk
+                .as_ref()
+                .context("Failed to get bootloader disk")?
+        );
+        Ok(Some(if legacy {
+            format!(
+                r#"  # Use the GRUB 2 boot loader.
+  boot.loader.grub.enable = true;
+{}"#,
+                device
+            )
+        } else {
+            device
+        }))
+    }
+}
+
+fn network(ctx: &TemplateContext) -> Result<Option<String>> {
+    Ok(Some(format!(
+        r#"  # Define your hostname.
+  networking.hostName = "{}";"#,
+        ctx.user
+            .as_ref()
+            .map(|x| x.hostname.as_ref())
+            .unwrap_or("nixos")
+    )))
+}
+
+fn timezone(ctx: &TemplateContext) -> Result<Option<String>> {
+    if ctx.imperative_timezone {
+        return Ok(Some(String::new()));
+    }
+    Ok(ctx.timezone.as_ref().map(|tz| {
+        format!(
+            r#"  # Set your time zone.
+  time.timeZone = "{}";"#,
+            tz
+        )
+    }))
+}
+
+fn locale(ctx: &TemplateContext) -> Result<Option<String>> {
+    let Some(locale) = &ctx.language else {
+        return Ok(None);
+    };
+    let mut localecfg = format!(
+        r#"  # Select internationalisation properties.
+  i18n.defaultLocale = "{}";"#,
+        locale
+    );
+    if let Some(formats) = ctx.formats.as_ref().filter(|formats| *formats != locale) {
+        localecfg.push_str("\n\n  # Use regional formats of another locale.\n");
+        localecfg.push_str("  i18n.extraLocaleSettings = {\n");
+        for category in FORMAT_CATEGORIES {
+            localecfg.push_str(&format!("    {} = \"{}\";\n", category, formats));
+        }
+        localecfg.push_str("  };\n");
+        let mut supported = vec!["C.UTF-8/UTF-8".to_string(), "en_US.UTF-8/UTF-8".to_string()];
+        for l in [locale, formats] {
+            let l = supported_locale(l);
+            if !supported.contains(&l) {
+                supported.push(l);
+            }
+        }
+        localecfg.push_str(&format!(
+            "  i18n.supportedLocales = [ {} ];",
+            supported
+                .iter()
+                .map(|l| format!("\"{}\"", l))
+                .collect::<Vec<_>>()
+                .join(" ")
+        ));
+    }
+    Ok(Some(localecfg))
+}
+
+/// Locale categories taken from the regional formats instead of the language
+const FORMAT_CATEGORIES: [&str; 9] = [
+    "LC_ADDRESS",
+    "LC_IDENTIFICATION",
+    "LC_MEASUREMENT",
+    "LC_MONETARY",
+    "LC_NAME",
+    "LC_NUMERIC",
+    "LC_PAPER",
+    "LC_TELEPHONE",
+    "LC_TIME",
+];
+
+fn keyboard(ctx: &TemplateContext) -> Result<Option<String>> {
+    let Some(keyboard) = &ctx.keyboard else {
+        return Ok(None);
+    };
+    let (layouts, variants) = keyboard.xkb_layouts();
+    let mut settings = vec![format!("    layout = \"{}\";", layouts)];
+    if variants.chars().any(|c| c != ',') {
+        settings.push(format!("    xkbVariant = \"{}\";", variants));
+    }
+    if let Some(options) = keyboard.xkb_options() {
+        settings.push(format!("    xkbOptions = \"{}\";", options));
+    }
+    let mut config = if settings.len() == 1 {
+        format!(
+            r#"  # Set the keyboard layout.
+  services.xserver.layout = "{}";
+  console.useXkbConfig = true;"#,
+            layouts
+        )
+    } else {
+        format!(
+            r#"  # Set the keyboard layout.
+  services.xserver = {{
+{}
+  }};
+  console.useXkbConfig = true;"#,
+            settings.join("\n")
+        )
+    };
+    // GNOME keeps its own list of layouts and ignores the X server's
+    if let Some(options) = keyboard.xkb_options() {
+        let sources = keyboard
+            .layouts
+            .iter()
+            .map(|layout| format!("('xkb', '{}')", layout))
+            .collect::<Vec<_>>()
+            .join(", ");
+        config.push_str(&format!(
+            r#"
+  services.xserver.desktopManager.gnome.extraGSettingsOverrides = ''
+    [org.gnome.desktop.input-sources]
+    sources=[{}]
+    xkb-options=['{}']
+  '';"#,
+            sources, options
+        ));
+    }
+    Ok(Some(config))
+}
+
+fn main_user(ctx: &TemplateContext) -> Option<&UserAccount> {
+    ctx.user.as_ref().and_then(|u| u.main_user())
+}
+
+/// Escapes a value for a double quoted Nix string
+fn nix_string(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace("${", "\\${")
+}
+
+fn users(ctx: &TemplateContext) -> Result<Option<String>> {
+    let Some(user) = &ctx.user else {
+        return Ok(None);
+    };
+    let mut blocks = vec![];
+    for account in &user.users {
+        let hash = ctx
+            .hashedpasswords
+            .get(&account.username)
+            .with_context(|| format!("No password hash for {}", account.username))?;
+        let shell = if account.shell == Shell::Bash {
+            String::new()
+        } else {
+            format!("    shell = pkgs.{};\n", account.shell.name())
+        };
+        blocks.push(format!(
+            r#"  users.users."{}" = {{
+    isNormalUser = true;
+    description = "{}";
+    extraGroups = [ {} ];
+{}{}    initialHashedPassword = "{}";
+  }};"#,
+            account.username,
+            nix_string(&account.name),
+            groups(account)
+                .iter()
+                .map(|g| format!("\"{}\"", g))
+                .collect::<Vec<_>>()
+                .join(" "),
+            shell,
+            authorized_keys(account),
+            hash
+        ));
+    }
+    // Login shells other than bash have to be enabled system wide
+    for shell in Shell::ALL {
+        if shell != Shell::Bash && user.users.iter().any(|u| u.shell == shell) {
+            blocks.push(format!("  programs.{}.enable = true;", shell.name()));
+        }
+    }
+    Ok(Some(format!(
+        "  # Define the user accounts.\n{}",
+        blocks.join("\n")
+    )))
+}
+
+fn authorized_keys(account: &UserAccount) -> String {
+    if account.authorized_keys.is_empty() {
+        return String::new();
+    }
+    format!(
+        "    openssh.authorizedKeys.keys = [\n{}\n    ];\n",
+        account
+            .authorized_keys
+            .iter()
+            .map(|key| format!("      \"{}\"", nix_string(key)))
+            .collect::<Vec<_>>()
+            .join("\n")
+    )
+}
+
+fn openssh(ctx: &TemplateContext) -> Result<Option<String>> {
+    let Some(user) = &ctx.user else {
+        return Ok(None);
+    };
+    Ok(Some(if user.openssh {
+        r#"  # Enable the OpenSSH daemon, only allowing logins with SSH keys.
+  services.openssh = {
+    enable = true;
+    settings.PasswordAuthentication = false;
+    settings.KbdInteractiveAuthentication = false;
+  };"#
+        .to_string()
+    } else {
+        String::new()
+    }))
+}
+
+/// Groups of a user, administrators are in `wheel`
+fn groups(account: &UserAccount) -> Vec<&str> {
+    let mut groups = vec![];
+    if account.admin {
+        groups.push("wheel");
+    }
+    groups.extend(["networkmanager", "dialout"]);
+    for group in &account.groups {
+        if !groups.contains(&group.as_str()) {
+            groups.push(group);
+        }
+    }
+    groups
+}
+
+fn rootpassword(ctx: &TemplateContext) -> Result<Option<String>> {
+    Ok(Some(match &ctx.roothashedpassword {
+        Some(hash) => format!(
+            r#"  # Set the root password.
+  users.users.root.initialHashedPassword = "{}";"#,
+            hash
+        ),
+        None => String::new(),
+    }))
+}
+
+fn autologin(ctx: &TemplateContext) -> Result<Option<String>> {
+    let (Some(user), Some(main)) = (&ctx.user, main_user(ctx)) else {
+        return Ok(None);
+    };
+    let mut autocfg = String::new();
+    if user.autologin {
+        autocfg.push_str(&format!(
+            r#"  # Enable automatic login for the user.
+  services.xserver.displayManager.autoLogin.enable = true;
+  services.xserver.displayManager.autoLogin.user = "{}";
+"#,
+            main.username
+        ));
+        autocfg.push_str(
+            r#"  # Workaround for GNOME autologin: https://github.com/NixOS/nixpkgs/issues/103746#issuecomment-945091229
+  systemd.services."getty@tty1".enable = false;
+  systemd.services."autovt@tty1".enable = false;
+"#,
+        );
+    }
+    Ok(Some(autocfg))
+}
+
+fn packages(ctx: &TemplateContext) -> Result<Option<String>> {
+    let mut pkgs: Vec<&String> = vec![];
+    // Sorted so that the output does not depend on the order of the choices
+    let mut listids = ctx.list.keys().collect::<Vec<_>>();
+    listids.sort();
+    for listid in listids {
+        let choices = &ctx.list[listid];
+        let mut keys = choices.keys().collect::<Vec<_>>();
+        keys.sort();
+        for key in keys {
+            if let Some(choicepkgs) = &choices[key].packages {
+                pkgs.extend(choicepkgs);
+            }
+        }
+    }
+    let mut extrapkgs: Vec<&String> = vec![];
+    for pkg in ctx.packages.iter().chain(pkgs) {
+        if !extrapkgs.contains(&pkg) {
+            extrapkgs.push(pkg);
+        }
+    }
+    Ok(Some(format!(
+        r#"  # List packages installed in system profile.
+  environment.systemPackages = with pkgs; [
+{}  ];"#,
+        extrapkgs
+            .iter()
+            .map(|pkg| format!("    {}\n", pkg))
+            .collect::<String>()
+    )))
+}
+
+fn substituters(ctx: &TemplateContext) -> Result<Option<String>> {
+    if ctx.substituters.is_empty() && ctx.trusted_public_keys.is_empty() {
+        return Ok(Some(String::new()));
+    }
+    let quote = |values: &[String]| {
+        values
+            .iter()
+            .map(|value| format!("\"{}\"", nix_string(value)))
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    Ok(Some(format!(
+        r#"  # Binary caches used besides cache.nixos.org
+  nix.settings = {{
+    substituters = [ {} ];
+    trusted-public-keys = [ {} ];
+  }};"#,
+        quote(&ctx.substituters),
+        quote(&ctx.trusted_public_keys)
+    )))
+}
+
+fn list(ctx: &TemplateContext, id: &str) -> String {
+    let mut listcfg = String::new();
+    if let Some(choices) = ctx.list.get(id) {
+        for choice in choices.values() {
+            if let Some(cfg) = &choice.config {
+                cfg.lines()
+                    .for_each(|x| listcfg.push_str(&format!("  {}\n", x)));
+            }
+        }
+    }
+    listcfg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ui::window::LayoutSwitch;
+
+    fn account(name: &str, username: &str) -> UserAccount {
+        UserAccount {
+            name: name.to_string(),
+            username: username.to_string(),
+            password: "secret".to_string(),
+            admin: false,
+            groups: vec![],
+            shell: Shell::Bash,
+            authorized_keys: vec![],
+            avatar: None,
+        }
+    }
+
+    fn context() -> TemplateContext {
+        let mut main = account("Alice Example", "alice");
+        main.admin = true;
+        let mut choices = HashMap::new();
+        choices.insert(
+            "Flatpak".to_string(),
+            Choice {
+                description: None,
+                packages: Some(vec!["flatpak".to_string()]),
+                config: Some("services.flatpak.enable = true;".to_string()),
+            },
+        );
+        TemplateContext {
+            arch: "x86_64".to_string(),
+            config_type: ConfigType::Flakes,
+            efi: true,
+            bootdisk: None,
+            language: Some("en_US.UTF-8".to_string()),
+            formats: None,
+            timezone: Some("Europe/Berlin".to_string()),
+            imperative_timezone: false,
+            keyboard: Some(KeyboardConfig {
+                layouts: vec!["us".to_string()],
+                switch: LayoutSwitch::default(),
+            }),
+            user: Some(UserConfig {
+                users: vec![main],
+                hostname: "snowflake".to_string(),
+                rootpassword: None,
+                autologin: false,
+                openssh: false,
+            }),
+            hashedpasswords: HashMap::from([("alice".to_string(), "$6$salt$hash".to_string())]),
+            roothashedpassword: None,
+            list: HashMap::from([("PACKAGEMANAGERS".to_string(), choices)]),
+            stateversion: "23.11".to_string(),
+            packages: vec!["git".to_string()],
+            substituters: vec![],
+            trusted_public_keys: vec![],
+        }
+    }
+
+    fn try_render(template: &str, ctx: &TemplateContext) -> Result<Rendered> {
+        PlaceholderRegistry::builtin(ctx.list.keys()).render(template, ctx)
+    }
+
+    /// Renders a template that only uses placeholders with a value
+    fn render(template: &str, ctx: &TemplateContext) -> String {
+        let rendered = try_render(template, ctx).unwrap();
+        assert!(rendered.unknown.is_empty(), "{:?}", rendered);
+        assert!(rendered.unreplaced.is_empty(), "{:?}", rendered);
+        rendered.text
+    }
+
+    #[test]
+    fn nvidiaoffload() {
+        assert_eq!(render("@NVIDIAOFFLOAD@", &context()), "");
+    }
+
+    #[test]
+    fn arch() {
+        assert_eq!(render("@ARCH@", &context()), "x86_64-linux");
+    }
+
+    #[test]
+    fn bootloader() {
+        let mut ctx = context();
+        assert_eq!(render("@BOOTLOADER@", &ctx), "");
+        ctx.config_type = ConfigType::Legacy;
+        assert!(render("@BOOTLOADER@", &ctx).contains("boot.loader.systemd-boot.enable = true;"));
+        ctx.efi = false;
+        ctx.bootdisk = Some("/dev/sda".to_string());
+        assert_eq!(
+            render("@BOOTLOADER@", &ctx),
+            "  # Use the GRUB 2 boot loader.\n  boot.loader.grub.enable = true;\n  boot.loader.grub.device = \"/dev/sda\";"
+        );
+        ctx.config_type = ConfigType::Flakes;
+        assert_eq!(
+            render("@BOOTLOADER@", &ctx),
+            "  boot.loader.grub.device = \"/dev/sda\";"
+        );
+        ctx.bootdisk = None;
+        assert!(try_render("@BOOTLOADER@", &ctx).is_err());
+    }
+
+    #[test]
+    fn bootloader_module() {
+        let mut ctx = context();
+        assert_eq!(
+            render("@BOOTLOADER_MODULE@", &ctx),
+            "snowflakeos-modules.nixosModules.efiboot"
+        );
+        ctx.efi = false;
+        assert_eq!(
+            render("@BOOTLOADER_MODULE@", &ctx),
+            "snowflakeos-modules.nixosModules.biosboot"
+        );
+    }
+
+    #[test]
+    fn network() {
+        let mut ctx = context();
+        assert_eq!(
+            render("@NETWORK@", &ctx),
+            "  # Define your hostname.\n  networking.hostName = \"snowflake\";"
+        );
+        ctx.user = None;
+        assert!(render("@NETWORK@", &ctx).contains("networking.hostName = \"nixos\";"));
+    }
+
+    #[test]
+    fn timezone() {
+        let mut ctx = context();
+        assert_eq!(
+            render("@TIMEZONE@", &ctx),
+            "  # Set your time zone.\n  time.timeZone = \"Europe/Berlin\";"
+        );
+        ctx.imperative_timezone = true;
+        assert_eq!(render("@TIMEZONE@", &ctx), "");
+    }
+
+    #[test]
+    fn locale() {
+        let mut ctx = context();
+        assert_eq!(
+            render("@LOCALE@", &ctx),
+            "  # Select internationalisation properties.\n  i18n.defaultLocale = \"en_US.UTF-8\";"
+        );
+        ctx.formats = Some("en_US.UTF-8".to_string());
+        assert!(!render("@LOCALE@", &ctx).contains("extraLocaleSettings"));
+        ctx.formats = Some("de_DE.UTF-8".to_string());
+        let text = render("@LOCALE@", &ctx);
+        for category in FORMAT_CATEGORIES {
+            assert!(text.contains(&format!("    {} = \"de_DE.UTF-8\";", category)));
+        }
+        assert!(text.contains(
+            r#"  i18n.supportedLocales = [ "C.UTF-8/UTF-8" "en_US.UTF-8/UTF-8" "de_DE.UTF-8/UTF-8" ];"#
+        ));
+    }
+
+    #[test]
+    fn keyboard() {
+        let mut ctx = context();
+        assert_eq!(
+            render("@KEYBOARD@", &ctx),
+            "  # Set the keyboard layout.\n  services.xserver.layout = \"us\";\n  console.useXkbConfig = true;"
+        );
+        ctx.keyboard = Some(KeyboardConfig {
+            layouts: vec!["us".to_string(), "ru+phonetic".to_string()],
+            switch: LayoutSwitch::AltShift,
+        });
+        let text = render("@KEYBOARD@", &ctx);
+        assert!(text.contains("    layout = \"us,ru\";"));
+        assert!(text.contains("    xkbVariant = \",phonetic\";"));
+        assert!(text.contains("    xkbOptions = \"grp:alt_shift_toggle\";"));
+        assert!(text.contains("    sources=[('xkb', 'us'), ('xkb', 'ru+phonetic')]"));
+        assert!(text.contains("    xkb-options=['grp:alt_shift_toggle']"));
+    }
+
+    #[test]
+    fn main_user_values() {
+        let ctx = context();
+        assert_eq!(render("@USERNAME@", &ctx), "alice");
+        assert_eq!(render("@FULLNAME@", &ctx), "Alice Example");
+        assert_eq!(render("@HOSTNAME@", &ctx), "snowflake");
+        assert_eq!(render("@HASHEDPASSWORD@", &ctx), "$6$salt$hash");
+    }
+
+    #[test]
+    fn users() {
+        let mut ctx = context();
+        assert_eq!(
+            render("@USERS@", &ctx),
+            r#"  # Define the user accounts.
+  users.users."alice" = {
+    isNormalUser = true;
+    description = "Alice Example";
+    extraGroups = [ "wheel" "networkmanager" "dialout" ];
+    initialHashedPassword = "$6$salt$hash";
+  };"#
+        );
+        let mut bob = account("Bob \"The Builder\" ${x}", "bob");
+        bob.shell = Shell::Zsh;
+        bob.groups = vec!["docker".to_string(), "dialout".to_string()];
+        bob.authorized_keys = vec!["ssh-ed25519 AAAA bob@host".to_string()];
+        ctx.user.as_mut().unwrap().users.push(bob);
+        ctx.hashedpasswords
+            .insert("bob".to_string(), "$6$salt$bob".to_string());
+        let text = render("@USERS@", &ctx);
+        assert!(text.contains(r#"    description = "Bob \"The Builder\" \${x}";"#));
+        assert!(text.contains(r#"    extraGroups = [ "networkmanager" "dialout" "docker" ];"#));
+        assert!(text.contains("    shell = pkgs.zsh;\n"));
+        assert!(text.contains("      \"ssh-ed25519 AAAA bob@host\"\n"));
+        assert!(text.ends_with("\n  programs.zsh.enable = true;"));
+        ctx.hashedpasswords.remove("bob");
+        assert!(try_render("@USERS@", &ctx).is_err());
+    }
+
+    #[test]
+    fn openssh() {
+        let mut ctx = context();
+        assert_eq!(render("@OPENSSH@", &ctx), "");
+        ctx.user.as_mut().unwrap().openssh = true;
+        let text = render("@OPENSSH@", &ctx);
+        assert!(text.contains("services.openssh = {"));
+        assert!(text.contains("settings.PasswordAuthentication = false;"));
+    }
+
+    #[test]
+    fn rootpassword() {
+        let mut ctx = context();
+        assert_eq!(render("@ROOTPASSWORD@", &ctx), "");
+        ctx.roothashedpassword = Some("$6$salt$root".to_string());
+        assert_eq!(
+            render("@ROOTPASSWORD@", &ctx),
+            "  # Set the root password.\n  users.users.root.initialHashedPassword = \"$6$salt$root\";"
+        );
+    }
+
+    #[test]
+    fn autologin() {
+        let mut ctx = context();
+        assert_eq!(render("@AUTOLOGIN@", &ctx), "");
+        ctx.user.as_mut().unwrap().autologin = true;
+        let text = render("@AUTOLOGIN@", &ctx);
+        assert!(text.contains("services.xserver.displayManager.autoLogin.user = \"alice\";"));
+        assert!(text.contains("systemd.services.\"getty@tty1\".enable = false;"));
+    }
+
+    #[test]
+    fn packages() {
+        let mut ctx = context();
+        ctx.packages.push("flatpak".to_string());
+        assert_eq!(
+            render("@PACKAGES@", &ctx),
+            "  # List packages installed in system profile.\n  environment.systemPackages = with pkgs; [\n    git\n    flatpak\n  ];"
+        );
+    }
+
+    #[test]
+    fn substituters() {
+        let mut ctx = context();
+        assert_eq!(render("@SUBSTITUTERS@", &ctx), "");
+        ctx.substituters = vec!["https://cache.example.org".to_string()];
+        ctx.trusted_public_keys = vec!["cache.example.org-1:AAAA".to_string()];
+        let text = render("@SUBSTITUTERS@", &ctx);
+        assert!(text.contains(r#"    substituters = [ "https://cache.example.org" ];"#));
+        assert!(text.contains(r#"    trusted-public-keys = [ "cache.example.org-1:AAAA" ];"#));
+        ctx.substituters = vec![r#"https://cache.example.org/?a="${b}"\"#.to_string()];
+        let text = render("@SUBSTITUTERS@", &ctx);
+        assert!(
+            text.contains(r#"    substituters = [ "https://cache.example.org/?a=\"\${b}\"\\" ];"#)
+        );
+    }
+
+    #[test]
+    fn stateversion() {
+        assert_eq!(
+            render("@STATEVERSION@", &context()),
+            "  system.stateVersion = \"23.11\"; # Did you read the comment?"
+        );
+    }
+
+    #[test]
+    fn list() {
+        assert_eq!(
+            render("@PACKAGEMANAGERS@", &context()),
+            "  services.flatpak.enable = true;\n"
+        );
+    }
+
+    #[test]
+    fn reports_unknown_and_unreplaced() {
+        let mut ctx = context();
+        ctx.timezone = None;
+        let template = "@FOO@ @HOSTNAME@ @TIMEZONE@ @FOO@ @TIMEZONE@ foo@example.org @@";
+        let rendered = try_render(template, &ctx).unwrap();
+        assert_eq!(
+            rendered.text,
+            "@FOO@ snowflake @TIMEZONE@ @FOO@ @TIMEZONE@ foo@example.org @@"
+        );
+        assert_eq!(rendered.unknown, vec!["FOO".to_string()]);
+        assert_eq!(rendered.unreplaced, vec!["TIMEZONE".to_string()]);
+    }
+}
@@ -0,0 +1,166 @@
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+/// Writes text in ASCII, for example to suggest a username from a full name.
+/// Latin letters without a decomposition as well as Cyrillic and Greek
+/// letters are looked up in a table, other letters are decomposed and their
+/// accents dropped, and anything else is dropped.
+pub fn to_ascii(text: &str) -> String {
+    let mut ascii = String::new();
+    for c in text.chars() {
+        if c.is_ascii() {
+            ascii.push(c);
+        } else if !push_letter(&mut ascii, c) {
+            // Letters like 'й' are in the table as they are, not decomposed
+            for c in std::iter::once(c).nfd() {
+                if c.is_ascii() {
+                    ascii.push(c);
+                } else if !is_combining_mark(c) {
+                    push_letter(&mut ascii, c);
+                }
+            }
+        }
+    }
+    ascii
+}
+
+/// Adds the ASCII spelling of a letter found in the tables, keeping it
+/// uppercase if it was. Returns whether the letter was found.
+fn push_letter(ascii: &mut String, c: char) -> bool {
+    let Some(latin) = c.to_lowercase().next().and_then(|lower| {
+        latin(lower)
+            .or_else(|| cyrillic(lower))
+            .or_else(|| greek(lower))
+    }) else {
+        return false;
+    };
+    if c.is_uppercase() {
+        let mut chars = latin.chars();
+        if let Some(first) = chars.next() {
+            ascii.push(first.to_ascii_uppercase());
+            ascii.push_str(chars.as_str());
+        }
+    } else {
+        ascii.push_str(latin);
+    }
+    true
+}
+
+/// Latin letters that have no decomposition
+fn latin(c: char) -> Option<&'static str> {
+    Some(match c {
+        'ß' => "ss",
+        'æ' => "ae",
+        'œ' => "oe",
+        'ø' => "o",
+        'ł' => "l",
+        'đ' | 'ð' => "d",
+        'þ' => "th",
+        'ħ' => "h",
+        'ı' => "i",
+        'ŀ' => "l",
+        'ŧ' => "t",
+        'ŋ' => "ng",
+        _ => return None,
+    })
+}
+
+fn cyrillic(c: char) -> Option<&'static str> {
+    Some(match c {
+        'а' => "a",
+        'б' => "b",
+        'в' => "v",
+        'г' | 'ґ' | 'ѓ' => "g",
+        'д' => "d",
+        'е' | 'ё' | 'э' => "e",
+        'є' => "ye",
+        'ж' => "zh",
+        'з' => "z",
+        'и' | 'і' => "i",
+        'ї' => "yi",
+        'й' | 'ы' => "y",
+        'ј' => "j",
+        'к' | 'ќ' => "k",
+        'л' => "l",
+        'љ' => "lj",
+        'м' => "m",
+        'н' => "n",
+        'њ' => "nj",
+        'о' => "o",
+        'п' => "p",
+        'р' => "r",
+        'с' => "s",
+        'т' => "t",
+        'ћ' => "c",
+        'ђ' => "dj",
+        'у' | 'ў' => "u",
+        'ф' => "f",
+        'х' => "kh",
+        'ц' => "ts",
+        'ч' => "ch",
+        'џ' | 'ѕ' => "dz",
+        'ш' => "sh",
+        'щ' => "shch",
+        'ъ' | 'ь' => "",
+        'ю' => "yu",
+        'я' => "ya",
+        _ => return None,
+    })
+}
+
+fn greek(c: char) -> Option<&'static str> {
+    Some(match c {
+        'α' => "a",
+        'β' => "v",
+        'γ' => "g",
+        'δ' => "d",
+        'ε' => "e",
+        'ζ' => "z",
+        'η' | 'ι' => "i",
+        'θ' => "th",
+        'κ' => "k",
+        'λ' => "l",
+        'μ' => "m",
+        'ν' => "n",
+        'ξ' => "x",
+        'ο' | 'ω' => "o",
+        'π' => "p",
+        'ρ' => "r",
+        'σ' | 'ς' => "s",
+        'τ' => "t",
+        'υ' => "y",
+        'φ' => "f",
+        'χ' => "ch",
+        'ψ' => "ps",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latin() {
+        assert_eq!(to_ascii("Łukasz Żółć"), "Lukasz Zolc");
+        assert_eq!(to_ascii("José Müller-Groß"), "Jose Muller-Gross");
+        assert_eq!(to_ascii("Ærøskøbing"), "Aeroskobing");
+    }
+
+    #[test]
+    fn cyrillic() {
+        assert_eq!(to_ascii("Дмитрий"), "Dmitriy");
+        assert_eq!(to_ascii("Їжак"), "Yizhak");
+        assert_eq!(to_ascii("Щербаков Артём"), "Shcherbakov Artem");
+        assert_eq!(to_ascii("Объект"), "Obekt");
+    }
+
+    #[test]
+    fn greek() {
+        assert_eq!(to_ascii("Γιώργος"), "Giorgos");
+    }
+
+    #[test]
+    fn drops_other_scripts() {
+        assert_eq!(to_ascii("李 Wei"), " Wei");
+    }
+}
@@ -0,0 +1,427 @@
+use super::{
+    driver::{DriverAction, InstallDriver},
+    install::{
+        installavatars, installfiles, makeconfig, ConfigDestination, InstallAnswers, MakeConfig,
+    },
+    language::get_languages,
+    parse::{parse_config, ChoiceEnum, IcicleConfig, InstallationConfig, StepType},
+    password, ssh,
+    state::{CommandOutcome, InstallState},
+    validate,
+};
+use crate::ui::{
+    pages::partitions::PartitionSchema,
+    window::{KeyboardConfig, LayoutSwitch, UserConfig},
+};
+use anyhow::{anyhow, Context, Result};
+use gnome_desktop::{XkbInfo, XkbInfoExt};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Answers for an unattended installation, read from a YAML or JSON file
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct AnswerFile {
+    pub config_id: String,
+    pub language: Option<String>,
+    /// Locale for dates, numbers and currencies, defaults to `language`
+    pub formats: Option<String>,
+    /// Comma separated layouts, e.g. `us,ru+phonetic`
+    pub keyboard: Option<String>,
+    /// Keys switching between layouts, with more than one
+    #[serde(default)]
+    pub keyboard_switch: LayoutSwitch,
+    pub timezone: Option<String>,
+    pub partitions: Option<PartitionSchema>,
+    pub user: Option<UserConfig>,
+    /// Selected choices for each `!list` step, keyed by list id
+    #[serde(default)]
+    pub lists: HashMap<String, Vec<String>>,
+}
+
+pub fn parse_answers(path: &str) -> Result<AnswerFile> {
+    debug!("Parsing answer file {}", path);
+    let f = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path))?;
+    // YAML is a superset of JSON, so this handles both formats
+    let answers: AnswerFile =
+        serde_yaml::from_str(&f).with_context(|| format!("Failed to parse {}", path))?;
+    Ok(answers)
+}
+
+impl AnswerFile {
+    /// Checks every answer against the selected installation configuration
+    /// and converts them into the answers used by the install pipeline.
+    /// Disks are only checked for existence if `check_devices` is set.
+    pub fn resolve(
+        self,
+        icicle: &IcicleConfig,
+        check_devices: bool,
+    ) -> Result<(InstallationConfig, InstallAnswers)> {
+        let config = icicle
+            .choices
+            .iter()
+            .find_map(|choice| match choice {
+                ChoiceEnum::Configuration { file: _, config }
+                    if config.config_id == self.config_id =>
+                {
+                    Some(config.clone())
+                }
+                _ => None,
+            })
+            .ok_or_else(|| {
+                let ids = icicle
+                    .choices
+                    .iter()
+                    .filter_map(|choice| match choice {
+                        ChoiceEnum::Configuration { file: _, config } => {
+                            Some(config.config_id.to_string())
+                        }
+                        ChoiceEnum::Live => None,
+                    })
+                    .collect::<Vec<_>>();
+                anyhow!(
+                    "Unknown config_id '{}', available configurations: {}",
+                    self.config_id,
+                    ids.join(", ")
+                )
+            })?;
+
+        let mut errors = vec![];
+        let mut answers = InstallAnswers::default();
+
+        for step in &config.steps {
+            match step {
+                StepType::Welcome => {
+                    let languages = get_languages();
+                    let known = |locale: &str| {
+                        languages
+                            .values()
+                            .any(|locales| locales.contains_key(locale))
+                    };
+                    match &self.language {
+                        Some(language) => {
+                            if known(language) {
+                                answers.language = Some(language.to_string());
+                            } else {
+                                errors.push(format!("Unknown language '{}'", language));
+                            }
+                        }
+                        None => errors.push("Missing answer: language".to_string()),
+                    }
+                    if let Some(formats) = &self.formats {
+                        if known(formats) {
+                            answers.formats = Some(formats.to_string());
+                        } else {
+                            errors.push(format!("Unknown formats '{}'", formats));
+                        }
+                    }
+                }
+                StepType::Keyboard => match &self.keyboard {
+                    Some(keyboard) => {
+                        let xkb = XkbInfo::new();
+                        let layouts = keyboard
+                            .split(',')
+                            .map(|layout| layout.trim().to_string())
+                            .collect::<Vec<_>>();
+                        let unknown = layouts
+                            .iter()
+                            .filter(|layout| xkb.layout_info(layout).is_none())
+                            .collect::<Vec<_>>();
+                        if unknown.is_empty() {
+                            answers.keyboard = Some(KeyboardConfig {
+                                layouts,
+                                switch: self.keyboard_switch,
+                            });
+                        } else {
+                            for layout in unknown {
+                                errors.push(format!("Unknown keyboard layout '{}'", layout));
+                            }
+                        }
+                    }
+                    None => errors.push("Missing answer: keyboard".to_string()),
+                },
+                StepType::Location => match &self.timezone {
+                    Some(timezone) => {
+                        if glib::TimeZone::from_identifier(Some(timezone)).is_some() {
+                            answers.timezone = Some(timezone.to_string());
+                        } else {
+                            errors.push(format!("Unknown timezone '{}'", timezone));
+                        }
+                    }
+                    None => errors.push("Missing answer: timezone".to_string()),
+                },
+                StepType::User { root, hostname } => match &self.user {
+                    Some(user) => {
+                        let mut user = user.clone();
+                        if user.users.is_empty() {
+                            errors.push("Missing answer: user.users".to_string());
+                        }
+                        let mut usernames = HashSet::new();
+                        for (i, account) in user.users.iter().enumerate() {
+                            if account.name.is_empty() {
+                                errors.push(format!("Missing answer: user.users[{}].name", i));
+                            } else if let Err(e) = validate::check_fullname(&account.name) {
+                                errors.push(format!("Invalid name '{}': {}", account.name, e));
+                            }
+                            if account.username.is_empty() {
+                                errors.push(format!("Missing answer: user.users[{}].username", i));
+                            } else if let Err(e) = validate::check_username(&account.username) {
+                                errors.push(format!(
+                                    "Invalid username '{}': {}",
+                                    account.username, e
+                                ));
+                            } else if !usernames.insert(&account.username) {
+                                errors.push(format!("Duplicate username '{}'", account.username));
+                            }
+                            if account.password.is_empty() {
+                                errors.push(format!("Missing answer: user.users[{}].password", i));
+                            }
+                            for violation in password::policy_violations(
+                                &account.password,
+                                &account.username,
+                                &config.password_policy,
+                            ) {
+                                if config.password_policy.enforce {
+                                    errors.push(format!(
+                                        "Password of user '{}': {}",
+                                        account.username, violation
+                                    ));
+                                } else {
+                                    warn!("Password of user '{}': {}", account.username, violation);
+                                }
+                            }
+                            if let Some(avatar) = &account.avatar {
+                                if !Path::new(avatar).is_file() {
+                                    errors.push(format!(
+                                        "Avatar '{}' for user '{}' does not exist",
+                                        avatar, account.username
+                                    ));
+                                }
+                            }
+                            for key in &account.authorized_keys {
+                                if let Err(e) = ssh::check_key(key) {
+                                    errors.push(format!(
+                                        "Invalid SSH key for user '{}': {}",
+                                        account.username, e
+                                    ));
+                                }
+                            }
+                            for group in &account.groups {
+                                if let Err(e) = validate::check_group(group) {
+                                    errors.push(format!(
+                                        "Invalid group '{}' for user '{}': {}",
+                                        group, account.username, e
+                                    ));
+                                }
+                            }
+                        }
+                        if user.openssh && user.users.iter().all(|u| u.authorized_keys.is_empty()) {
+                            errors.push(
+                                "user.openssh needs at least one user with authorized_keys"
+                                    .to_string(),
+                            );
+                        }
+                        if !user.users.iter().any(|u| u.admin) && user.rootpassword.is_none() {
+                            errors.push(
+                                "At least one user must be an administrator when no root password is set"
+                                    .to_string(),
+                            );
+                        }
+                        if !root.unwrap_or(false) && user.rootpassword.is_some() {
+                            errors.push(format!(
+                                "Configuration '{}' does not allow setting a root password",
+                                config.config_id
+                            ));
+                        }
+                        if !hostname.unwrap_or(false) || user.hostname.is_empty() {
+                            user.hostname = icicle.default_hostname.to_string();
+                        }
+                        if let Err(e) = validate::check_hostname(&user.hostname) {
+                            errors.push(format!("Invalid hostname '{}': {}", user.hostname, e));
+                        }
+                        answers.user = Some(user);
+                    }
+                    None => errors.push("Missing answer: user".to_string()),
+                },
+                StepType::List {
+                    id,
+                    multiple,
+                    required,
+                    title: _,
+                    choices,
+                } => {
+                    let available = choices
+                        .iter()
+                        .flat_map(|choice| choice.iter())
+                        .collect::<HashMap<_, _>>();
+                    let selected = self.lists.get(id).cloned().unwrap_or_default();
+                    if *required && selected.is_empty() {
+                        errors.push(format!("Missing answer: lists.{}", id));
+                    }
+                    if !*multiple && selected.len() > 1 {
+                        errors.push(format!("List '{}' only allows a single choice", id));
+                    }
+                    let mut listconfig = HashMap::new();
+                    for key in selected {
+                        if let Some(choice) = available.get(&key) {
+                            listconfig.insert(key, (*choice).clone());
+                        } else {
+                            errors.push(format!("Unknown choice '{}' in list '{}'", key, id));
+                        }
+                    }
+                    answers.list.insert(id.to_string(), listconfig);
+                }
+                StepType::Partitioning => match &self.partitions {
+                    Some(partitions) => {
+                        if check_devices {
+                            errors.extend(check_partitions(partitions));
+                        }
+                        answers.partitions = Some(partitions.clone());
+                    }
+                    None => errors.push("Missing answer: partitions".to_string()),
+                },
+                StepType::Manual | StepType::Summary => {}
+            }
+        }
+
+        for id in self.lists.keys() {
+            if !answers.list.contains_key(id) {
+                errors.push(format!(
+                    "Configuration '{}' has no list '{}'",
+                    config.config_id, id
+                ));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok((config, answers))
+        } else {
+            Err(anyhow!("Invalid answer file:\n - {}", errors.join("\n - ")))
+        }
+    }
+}
+
+fn check_partitions(partitions: &PartitionSchema) -> Vec<String> {
+    let mut errors = vec![];
+    match partitions {
+        PartitionSchema::FullDisk(disk) => {
+            if !Path::new(disk).exists() {
+                errors.push(format!("Disk '{}' does not exist", disk));
+            }
+        }
+        PartitionSchema::Custom(partitions) => {
+            let efi = distinst_disks::Bootloader::detect() == distinst_disks::Bootloader::Efi;
+            let mountpoints = partitions
+                .values()
+                .filter_map(|p| p.mountpoint.as_deref())
+                .collect::<Vec<_>>();
+            if !mountpoints.contains(&"/") {
+                errors.push("Missing / partition".to_string());
+            }
+            if efi && !mountpoints.contains(&"/boot") {
+                errors.push("Missing /boot partition".to_string());
+            }
+            for (name, part) in partitions {
+                if !Path::new(name).exists() {
+                    errors.push(format!("Partition '{}' does not exist", name));
+                }
+                if !Path::new(&part.device).exists() {
+                    errors.push(format!("Disk '{}' does not exist", part.device));
+                }
+            }
+        }
+    }
+    errors
+}
+
+/// Installs without any window, driving the same steps as the installer pages.
+/// With `resume`, continues the previous installation from the step that failed.
+pub fn install(path: &str, resume: bool) -> Result<()> {
+    let icicle = parse_config().context("Failed to parse config")?;
+    let (config, answers) = parse_answers(path)?.resolve(&icicle, true)?;
+
+    let mut driver = if resume {
+        let state = InstallState::load()?.context("No installation to resume")?;
+        InstallDriver::resume(config, answers, state)?
+    } else {
+        info!("Starting unattended installation of '{}'", config.config_id);
+        InstallDriver::new(config, answers)?
+    };
+
+    let mut action = driver.run()?;
+    loop {
+        action = match action {
+            DriverAction::Next => driver.run()?,
+            DriverAction::Run(cmd) => {
+                // Like a shell that could not find the command
+                let status = run(&cmd).unwrap_or_else(|e| {
+                    error!("Failed to run {:?}: {:?}", cmd, e);
+                    127
+                });
+                match driver.command_finished(status)? {
+                    Some(action) => action,
+                    None => driver.run()?,
+                }
+            }
+            DriverAction::Wait(delay) => {
+                std::thread::sleep(delay);
+                driver.run()?
+            }
+            DriverAction::Failed(e) => {
+                return Err(e.context(format!(
+                    "{} failed, run again with --resume to retry it",
+                    driver.state().step.description()
+                )));
+            }
+            DriverAction::Finished => break,
+        };
+    }
+    info!("Installation finished");
+    for result in &driver.state().command_results {
+        match result.outcome {
+            CommandOutcome::Succeeded => {}
+            CommandOutcome::Failed => warn!(
+                "Skipped after failing {} time(s): {}",
+                result.attempts, result.label
+            ),
+            CommandOutcome::TimedOut => warn!("Skipped after timing out: {}", result.label),
+        }
+    }
+    Ok(())
+}
+
+/// Renders the configuration the answers would produce into `output`,
+/// without partitioning, privileges or running `nixos-install`
+pub fn dry_run(path: &str, output: &str) -> Result<()> {
+    let icicle = parse_config().context("Failed to parse config")?;
+    let (config, answers) = parse_answers(path)?.resolve(&icicle, false)?;
+    info!(
+        "Rendering configuration '{}' into {}",
+        config.config_id, output
+    );
+    fs::create_dir_all(output).with_context(|| format!("Failed to create {}", output))?;
+    let dirconfig = MakeConfig::new(
+        &config,
+        &answers,
+        ConfigDestination::Directory(PathBuf::from(output)),
+    );
+    makeconfig(&dirconfig).context("Failed to make config")?;
+    installfiles(&dirconfig, &config.files).context("Failed to install files")?;
+    installavatars(&dirconfig).context("Failed to install user avatars")?;
+    info!("Configuration written to {}", output);
+    Ok(())
+}
+
+/// Runs a command to completion, returning its exit code
+fn run(cmd: &[String]) -> Result<i32> {
+    debug!("Running: {:?}", cmd);
+    let (program, args) = cmd.split_first().context("Empty command")?;
+    let status = Command::new(program).args(args).status()?;
+    // Killed by a signal
+    Ok(status.code().unwrap_or(-1))
+}
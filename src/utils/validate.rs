@@ -0,0 +1,229 @@
+use crate::utils::i18n::i18n_f;
+use anyhow::{bail, Result};
+use gettextrs::gettext;
+
+/// Longest username `useradd` accepts by default
+pub const USERNAME_MAX: usize = 32;
+
+/// Longest hostname, a single DNS label
+const HOSTNAME_MAX: usize = 63;
+
+/// Users and groups that NixOS or common services create themselves
+const RESERVED_NAMES: &[&str] = &[
+    "root",
+    "nobody",
+    "nogroup",
+    "daemon",
+    "bin",
+    "sys",
+    "adm",
+    "messagebus",
+    "polkituser",
+    "sshd",
+    "nscd",
+    "avahi",
+    "rtkit",
+    "geoclue",
+    "gdm",
+    "sddm",
+    "lightdm",
+    "colord",
+    "pulse",
+    "pipewire",
+    "flatpak",
+    "fwupd-refresh",
+    "nm-openvpn",
+    "nm-iodine",
+    "users",
+    "wheel",
+    "networkmanager",
+    "dialout",
+    "audio",
+    "video",
+    "input",
+    "render",
+    "disk",
+    "kvm",
+    "tty",
+    "utmp",
+    "keys",
+    "shadow",
+];
+
+/// Prefixes of names used for numbered or service accounts
+const RESERVED_PREFIXES: &[&str] = &["nixbld", "systemd-"];
+
+/// Checks a username against the rules of `useradd` and the accounts NixOS
+/// already has. The error explains why the name was rejected.
+pub fn check_username(username: &str) -> Result<()> {
+    check_name(username)?;
+    if username.chars().count() > USERNAME_MAX {
+        bail!(i18n_f(
+            "Usernames can be at most {} characters long",
+            &[&USERNAME_MAX.to_string()]
+        ));
+    }
+    if RESERVED_NAMES.contains(&username)
+        || RESERVED_PREFIXES
+            .iter()
+            .any(|prefix| username.starts_with(prefix))
+    {
+        bail!(i18n_f("“{}” is reserved by the system", &[username]));
+    }
+    Ok(())
+}
+
+/// Group names follow the same rules as usernames
+pub fn check_group(group: &str) -> Result<()> {
+    check_name(group)
+}
+
+fn check_name(name: &str) -> Result<()> {
+    let Some(first) = name.chars().next() else {
+        bail!(gettext("Must not be empty"));
+    };
+    if first.is_ascii_digit() || first == '-' {
+        bail!(gettext("Must start with a lowercase letter"));
+    }
+    if let Some(c) = name
+        .chars()
+        .find(|c| !(c.is_ascii_lowercase() || c.is_ascii_digit() || *c == '_' || *c == '-'))
+    {
+        bail!(i18n_f(
+            "“{}” is not allowed, only lowercase letters, digits, “-” and “_” are",
+            &[&c.to_string()]
+        ));
+    }
+    Ok(())
+}
+
+/// Checks a hostname against RFC 1123. NixOS only takes a single label,
+/// without a domain.
+pub fn check_hostname(hostname: &str) -> Result<()> {
+    if hostname.is_empty() {
+        bail!(gettext("Must not be empty"));
+    }
+    if hostname.len() > HOSTNAME_MAX {
+        bail!(i18n_f(
+            "Hostnames can be at most {} characters long",
+            &[&HOSTNAME_MAX.to_string()]
+        ));
+    }
+    if hostname.contains('.') {
+        bail!(gettext("Must not contain a domain"));
+    }
+    if let Some(c) = hostname
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || *c == '-'))
+    {
+        bail!(i18n_f(
+            "“{}” is not allowed, only letters, digits and “-” are",
+            &[&c.to_string()]
+        ));
+    }
+    if hostname.starts_with('-') || hostname.ends_with('-') {
+        bail!(gettext("Must not start or end with “-”"));
+    }
+    Ok(())
+}
+
+/// Full names end up in `/etc/passwd`, where `:` separates fields
+pub fn check_fullname(name: &str) -> Result<()> {
+    if name.trim().is_empty() {
+        bail!(gettext("Must not be empty"));
+    }
+    if name.contains(':') || name.chars().any(|c| c.is_control()) {
+        bail!(gettext("Must not contain “:” or control characters"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(result: Result<()>) -> String {
+        result.unwrap_err().to_string()
+    }
+
+    #[test]
+    fn username() {
+        assert!(check_username("alice").is_ok());
+        assert!(check_username("a_l-1ce").is_ok());
+        assert!(check_username(&"a".repeat(USERNAME_MAX)).is_ok());
+        assert_eq!(error(check_username("")), "Must not be empty");
+        assert_eq!(
+            error(check_username("1alice")),
+            "Must start with a lowercase letter"
+        );
+        assert_eq!(
+            error(check_username("-alice")),
+            "Must start with a lowercase letter"
+        );
+        assert_eq!(
+            error(check_username("Alice")),
+            "“A” is not allowed, only lowercase letters, digits, “-” and “_” are"
+        );
+        assert_eq!(
+            error(check_username(&"a".repeat(USERNAME_MAX + 1))),
+            "Usernames can be at most 32 characters long"
+        );
+        assert_eq!(
+            error(check_username("root")),
+            "“root” is reserved by the system"
+        );
+        assert_eq!(
+            error(check_username("nixbld1")),
+            "“nixbld1” is reserved by the system"
+        );
+    }
+
+    #[test]
+    fn group() {
+        assert!(check_group("docker").is_ok());
+        // Groups of the system are meant to be joined
+        assert!(check_group("wheel").is_ok());
+        assert_eq!(
+            error(check_group("-docker")),
+            "Must start with a lowercase letter"
+        );
+    }
+
+    #[test]
+    fn hostname() {
+        assert!(check_hostname("snowflake").is_ok());
+        assert!(check_hostname("Snow-Flake2").is_ok());
+        assert!(check_hostname(&"a".repeat(63)).is_ok());
+        assert_eq!(error(check_hostname("")), "Must not be empty");
+        assert_eq!(
+            error(check_hostname(&"a".repeat(64))),
+            "Hostnames can be at most 63 characters long"
+        );
+        assert_eq!(
+            error(check_hostname("snowflake.local")),
+            "Must not contain a domain"
+        );
+        assert_eq!(
+            error(check_hostname("snow_flake")),
+            "“_” is not allowed, only letters, digits and “-” are"
+        );
+        assert_eq!(
+            error(check_hostname("-snowflake")),
+            "Must not start or end with “-”"
+        );
+    }
+
+    #[test]
+    fn fullname() {
+        assert!(check_fullname("Łukasz Żółć").is_ok());
+        assert_eq!(error(check_fullname("  ")), "Must not be empty");
+        assert_eq!(
+            error(check_fullname("Alice: Admin")),
+            "Must not contain “:” or control characters"
+        );
+        assert_eq!(
+            error(check_fullname("Alice\n")),
+            "Must not contain “:” or control characters"
+        );
+    }
+}